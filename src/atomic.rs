@@ -0,0 +1,82 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::{hexstring, Result};
+
+/// Output file that only appears at its destination once committed
+///
+/// Data goes to a hidden temporary sibling of the destination. [`commit`]
+/// syncs it to disk and renames it into place; dropping an uncommitted file
+/// removes the temporary instead, so a failed run never leaves a partial or
+/// truncated destination behind.
+///
+/// [`commit`]: AtomicFile::commit
+pub struct AtomicFile {
+    file: Option<BufWriter<File>>,
+    tmp_path: PathBuf,
+    dest: PathBuf,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file next to `dest`, creating parent directories as needed.
+    pub fn create(dest: &Path) -> Result<Self> {
+        let parent = dest.parent().unwrap_or(Path::new(""));
+        fs::create_dir_all(parent)?;
+        let name = dest.file_name().unwrap_or_default().to_string_lossy();
+        loop {
+            // a fresh name every time, since several jobs may write to the
+            // same destination and create_new refuses to follow a planted link
+            let mut suffix = [0u8; 8];
+            getrandom::getrandom(&mut suffix).map_err(io::Error::from)?;
+            let tmp_path = parent.join(format!(".{}.easycrab-{}.tmp", name, hexstring(&suffix)));
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&tmp_path)
+            {
+                Ok(file) => {
+                    return Ok(AtomicFile {
+                        file: Some(BufWriter::new(file)),
+                        tmp_path,
+                        dest: dest.to_path_buf(),
+                    })
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Flushes and syncs the data, then renames it over the destination.
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().expect("file is present until commit");
+        let file = file.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.tmp_path, &self.dest)?;
+        Ok(())
+    }
+
+    fn writer(&mut self) -> &mut BufWriter<File> {
+        self.file.as_mut().expect("file is present until commit")
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// Runs `work` over `items` on up to `jobs` threads.
+///
+/// Results are handed to `report` in the original order of `items`, as soon
+/// as every earlier item has finished.
+pub fn run_ordered<I, T, W, F>(items: &[I], jobs: NonZeroUsize, work: W, mut report: F)
+where
+    I: Sync,
+    T: Send,
+    W: Fn(&I) -> T + Sync,
+    F: FnMut(&I, T),
+{
+    let jobs = jobs.get().min(items.len());
+    if jobs <= 1 {
+        for item in items {
+            report(item, work(item));
+        }
+        return;
+    }
+
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|s| {
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (next, work) = (&next, &work);
+            s.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else { break };
+                if tx.send((i, work(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        let mut pending = BTreeMap::new();
+        let mut next_report = 0;
+        for (i, result) in rx {
+            pending.insert(i, result);
+            while let Some(result) = pending.remove(&next_report) {
+                report(&items[next_report], result);
+                next_report += 1;
+            }
+        }
+    });
+}
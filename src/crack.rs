@@ -0,0 +1,294 @@
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::EzcHeader;
+
+const BATCH_SIZE: usize = 256;
+
+/// Transformation applied to every wordlist entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    /// "password" -> "Password"
+    Capitalize,
+    /// "password" -> "PASSWORD"
+    Upper,
+    /// "password" -> "drowssap"
+    Reverse,
+    /// "password" -> "password0" .. "password9"
+    AppendDigit,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "capitalize" => Ok(Rule::Capitalize),
+            "upper" => Ok(Rule::Upper),
+            "reverse" => Ok(Rule::Reverse),
+            "append-digit" => Ok(Rule::AppendDigit),
+            _ => Err(format!(
+                "unknown rule '{}' (expected capitalize, upper, reverse or append-digit)",
+                s
+            )),
+        }
+    }
+}
+
+impl Rule {
+    fn apply(self, word: &[u8], out: &mut Vec<Vec<u8>>) {
+        match self {
+            Rule::Capitalize => {
+                let mut w = word.to_ascii_lowercase();
+                if let Some(c) = w.first_mut() {
+                    c.make_ascii_uppercase();
+                }
+                out.push(w);
+            }
+            Rule::Upper => out.push(word.to_ascii_uppercase()),
+            Rule::Reverse => out.push(word.iter().rev().copied().collect()),
+            Rule::AppendDigit => {
+                for d in b'0'..=b'9' {
+                    let mut w = word.to_vec();
+                    w.push(d);
+                    out.push(w);
+                }
+            }
+        }
+    }
+}
+
+/// Expands each word into itself followed by every rule's variants.
+pub fn apply_rules<'a, I>(words: I, rules: &'a [Rule]) -> impl Iterator<Item = Vec<u8>> + 'a
+where
+    I: IntoIterator<Item = Vec<u8>> + 'a,
+{
+    words.into_iter().flat_map(move |word| {
+        let mut out = Vec::with_capacity(rules.len() + 1);
+        for rule in rules {
+            rule.apply(&word, &mut out);
+        }
+        out.insert(0, word);
+        out
+    })
+}
+
+/// Candidate generator for hashcat-style masks
+///
+/// `?l`, `?u`, `?d`, `?s` and `?a` stand for lowercase, uppercase, digits,
+/// symbols and all printable ASCII; `??` is a literal `?` and any other
+/// character matches itself.
+#[derive(Clone, Debug)]
+pub struct Mask {
+    charsets: Vec<Vec<u8>>,
+}
+
+impl FromStr for Mask {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower: Vec<u8> = (b'a'..=b'z').collect();
+        let upper: Vec<u8> = (b'A'..=b'Z').collect();
+        let digit: Vec<u8> = (b'0'..=b'9').collect();
+        let symbol: Vec<u8> = (b' '..=b'~')
+            .filter(|c| !c.is_ascii_alphanumeric())
+            .collect();
+        let all: Vec<u8> = (b' '..=b'~').collect();
+
+        let mut charsets = Vec::new();
+        let mut bytes = s.bytes();
+        while let Some(c) = bytes.next() {
+            if c != b'?' {
+                charsets.push(vec![c]);
+                continue;
+            }
+            charsets.push(match bytes.next() {
+                Some(b'l') => lower.clone(),
+                Some(b'u') => upper.clone(),
+                Some(b'd') => digit.clone(),
+                Some(b's') => symbol.clone(),
+                Some(b'a') => all.clone(),
+                Some(b'?') => vec![b'?'],
+                Some(c) => return Err(format!("unknown mask charset '?{}'", c as char)),
+                None => return Err("mask ends with a lone '?'".to_string()),
+            });
+        }
+        Ok(Mask { charsets })
+    }
+}
+
+impl Mask {
+    /// Number of candidates the mask expands to
+    pub fn keyspace(&self) -> u128 {
+        self.charsets.iter().map(|c| c.len() as u128).product()
+    }
+
+    pub fn candidates(&self) -> MaskCandidates<'_> {
+        MaskCandidates {
+            mask: self,
+            indices: vec![0; self.charsets.len()],
+            done: false,
+        }
+    }
+}
+
+pub struct MaskCandidates<'a> {
+    mask: &'a Mask,
+    indices: Vec<usize>,
+    done: bool,
+}
+
+impl Iterator for MaskCandidates<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        if self.done {
+            return None;
+        }
+        let candidate = self
+            .indices
+            .iter()
+            .zip(&self.mask.charsets)
+            .map(|(&i, set)| set[i])
+            .collect();
+
+        // odometer increment, rightmost position first
+        self.done = true;
+        for (i, set) in self.indices.iter_mut().zip(&self.mask.charsets).rev() {
+            *i += 1;
+            if *i < set.len() {
+                self.done = false;
+                break;
+            }
+            *i = 0;
+        }
+        Some(candidate)
+    }
+}
+
+/// Tests `candidates` against the stored SHA-512 verifier on `jobs` threads.
+///
+/// Returns the first candidate found to match, if any.
+pub fn find_password<I>(header: &EzcHeader, candidates: I, jobs: NonZeroUsize) -> Option<Vec<u8>>
+where
+    I: Iterator<Item = Vec<u8>> + Send,
+{
+    let candidates = Mutex::new(candidates);
+    let found = AtomicBool::new(false);
+    let result = Mutex::new(None);
+
+    thread::scope(|s| {
+        for _ in 0..jobs.get() {
+            s.spawn(|| {
+                let mut batch = Vec::with_capacity(BATCH_SIZE);
+                while !found.load(Ordering::Relaxed) {
+                    batch.clear();
+                    batch.extend(candidates.lock().unwrap().by_ref().take(BATCH_SIZE));
+                    if batch.is_empty() {
+                        break;
+                    }
+                    if let Some(password) = batch.iter().find(|p| header.check_password(p)) {
+                        found.store(true, Ordering::Relaxed);
+                        *result.lock().unwrap() = Some(password.clone());
+                    }
+                }
+            });
+        }
+    });
+
+    result.into_inner().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encrypt, PASSWORD};
+
+    fn mask(s: &str) -> Mask {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn mask_parsing() {
+        let m = mask("a??b");
+        assert_eq!(m.keyspace(), 1);
+        assert_eq!(m.candidates().collect::<Vec<_>>(), [b"a?b".to_vec()]);
+        assert_eq!(mask("?a").keyspace(), 95);
+        assert_eq!(mask("?s").keyspace(), 33);
+        assert_eq!(mask("?l?u?d").keyspace(), 26 * 26 * 10);
+
+        assert_eq!(
+            "ab?".parse::<Mask>().unwrap_err(),
+            "mask ends with a lone '?'"
+        );
+        assert_eq!(
+            "?x".parse::<Mask>().unwrap_err(),
+            "unknown mask charset '?x'"
+        );
+    }
+
+    #[test]
+    fn mask_candidates() {
+        let m = mask("?d?d");
+        assert_eq!(m.keyspace(), 100);
+        let candidates: Vec<_> = m.candidates().collect();
+        let expected: Vec<_> = (0..100).map(|n| format!("{:02}", n).into_bytes()).collect();
+        assert_eq!(candidates, expected);
+
+        let m = mask("x?lz");
+        let candidates: Vec<_> = m.candidates().collect();
+        assert_eq!(candidates.len() as u128, m.keyspace());
+        assert_eq!(candidates[0], b"xaz");
+        assert_eq!(candidates[25], b"xzz");
+    }
+
+    #[test]
+    fn rules() {
+        let words = || vec![b"paSS".to_vec(), Vec::new()];
+        let apply = |rules: &[Rule]| apply_rules(words(), rules).collect::<Vec<_>>();
+
+        assert_eq!(apply(&[]), words());
+        assert_eq!(
+            apply(&[Rule::Capitalize]),
+            [&b"paSS"[..], b"Pass", b"", b""]
+        );
+        assert_eq!(apply(&[Rule::Upper]), [&b"paSS"[..], b"PASS", b"", b""]);
+        assert_eq!(apply(&[Rule::Reverse]), [&b"paSS"[..], b"SSap", b"", b""]);
+
+        let appended = apply(&[Rule::AppendDigit]);
+        assert_eq!(appended.len(), 22);
+        assert_eq!(appended[0], b"paSS");
+        assert_eq!(appended[1], b"paSS0");
+        assert_eq!(appended[10], b"paSS9");
+        assert_eq!(appended[12], b"0");
+
+        // every rule applies to the original word, not to each other's output
+        assert_eq!(
+            apply(&[Rule::Upper, Rule::Reverse])[..3],
+            [&b"paSS"[..], b"PASS", b"SSap"]
+        );
+        assert_eq!("append-digit".parse(), Ok(Rule::AppendDigit));
+        assert!("lower".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn finds_password() {
+        let file = encrypt(b"");
+        let header = EzcHeader::read_from(&mut &file[..]).unwrap();
+        let jobs = NonZeroUsize::new(4).unwrap();
+
+        // more than one batch, so the threads share the work
+        let m = mask("?l?l");
+        let found = find_password(&header, m.candidates(), jobs);
+        assert_eq!(found.as_deref(), Some(PASSWORD));
+
+        assert_eq!(
+            find_password(&header, mask("?d?d").candidates(), jobs),
+            None
+        );
+        assert_eq!(find_password(&header, std::iter::empty(), jobs), None);
+    }
+}
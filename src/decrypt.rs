@@ -0,0 +1,280 @@
+use std::io::{prelude::*, SeekFrom};
+use std::num::NonZeroUsize;
+
+use digest::Digest;
+use sha1::Sha1;
+
+use crate::pipeline::{self, PipelineStats};
+use crate::{
+    EasyCrabError, EzcHeader, PayloadDecryptor, Result, SecretBuf, CHUNK_SIZE, DATA_OFFSET,
+};
+
+/// Sizes and checksums gathered while decrypting a file
+#[derive(Clone, Debug)]
+pub struct DecryptSummary {
+    pub major: u8,
+    pub minor: u8,
+    /// Length of the encrypted payload, padding included
+    pub data_length: u64,
+    /// Length of the plaintext after unpadding
+    pub plain_length: u64,
+    /// SHA-1 of the plaintext as stored in the encrypted trailer
+    pub src_checksum: [u8; 20],
+    /// SHA-1 of the plaintext actually produced
+    pub calc_checksum: [u8; 20],
+}
+
+impl DecryptSummary {
+    pub fn is_match(&self) -> bool {
+        self.src_checksum == self.calc_checksum
+    }
+
+    /// Turns a checksum mismatch into [`EasyCrabError::ChecksumMismatch`].
+    pub fn verify(&self) -> Result<()> {
+        if self.is_match() {
+            Ok(())
+        } else {
+            Err(EasyCrabError::ChecksumMismatch)
+        }
+    }
+}
+
+/// Streaming decrypter over an EasyCrypt V2.x file
+pub struct Decryptor<R> {
+    pub(crate) reader: R,
+    pub(crate) header: EzcHeader,
+    pub(crate) key: SecretBuf,
+    pub(crate) src_checksum: [u8; 20],
+    pub(crate) data_length: u64,
+}
+
+impl<R: Read + Seek> Decryptor<R> {
+    /// Parses the header and checks `password` against the stored verifier.
+    pub fn new(reader: R, password: &[u8]) -> Result<Self> {
+        let mut dec = Self::open(reader)?;
+        if !dec.header.check_password(password) {
+            return Err(EasyCrabError::WrongPassword);
+        }
+        dec.read_trailer()?;
+        Ok(dec)
+    }
+
+    /// Parses the header and decrypts without any password.
+    ///
+    /// EasyCrypt V2.x uses the first 32 bytes of the stored
+    /// SHA-512(password || salt) verifier as the AES-256 key, so the header
+    /// alone is enough to decrypt the payload.
+    pub fn from_header_key(reader: R) -> Result<Self> {
+        let mut dec = Self::open(reader)?;
+        dec.read_trailer()?;
+        Ok(dec)
+    }
+
+    fn open(mut reader: R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let header = EzcHeader::read_from(&mut reader)?;
+        let key = header.key();
+        Ok(Decryptor {
+            reader,
+            header,
+            key,
+            src_checksum: [0u8; 20],
+            data_length: 0,
+        })
+    }
+
+    fn read_trailer(&mut self) -> Result<()> {
+        let file_length = self.reader.seek(SeekFrom::End(0))?;
+        // at least one payload block and the trailer must follow the header
+        if file_length < DATA_OFFSET + 0x10 + 0x20 {
+            return Err(EasyCrabError::Truncated);
+        }
+        let checksum_offset = self.reader.seek(SeekFrom::End(-0x20))?;
+        let mut trailer = [0u8; 0x20];
+        self.reader.read_exact(&mut trailer)?;
+        self.data_length = checksum_offset - DATA_OFFSET;
+        if !self.data_length.is_multiple_of(0x10) {
+            return Err(EasyCrabError::Truncated);
+        }
+
+        self.src_checksum = self.header.decrypt_trailer(&trailer)?;
+        Ok(())
+    }
+
+    fn cipher(&self) -> PayloadDecryptor {
+        PayloadDecryptor::new(&self.key, &self.header.iv)
+    }
+
+    pub fn header(&self) -> &EzcHeader {
+        &self.header
+    }
+
+    /// SHA-1 of the plaintext as stored in the encrypted trailer
+    pub fn src_checksum(&self) -> &[u8; 20] {
+        &self.src_checksum
+    }
+
+    /// Length of the encrypted payload, padding included
+    pub fn data_length(&self) -> u64 {
+        self.data_length
+    }
+
+    /// Decrypts the payload into `out`, hashing the plaintext as it goes.
+    pub fn decrypt_to<W: Write>(&mut self, mut out: Option<W>) -> Result<DecryptSummary> {
+        let data_length = self.data_length;
+        self.reader.seek(SeekFrom::Start(DATA_OFFSET))?;
+        let mut payload = self.cipher();
+        let mut processed_bytes = 0;
+        let mut buf = SecretBuf::new(CHUNK_SIZE as usize);
+        let mut filehasher = Sha1::new();
+
+        for _ in 0..((data_length - 0x10) / CHUNK_SIZE) {
+            self.reader.read_exact(&mut buf)?;
+            payload.decrypt_chunk(&mut buf);
+            filehasher.update(&*buf);
+            if let Some(ref mut f) = out {
+                f.write_all(&buf)?;
+            }
+            processed_bytes += CHUNK_SIZE;
+        }
+
+        let last = &mut buf[0..(data_length - processed_bytes) as usize];
+        self.reader.read_exact(last)?;
+        let unpadded = payload.finish(last)?;
+        filehasher.update(unpadded);
+        if let Some(ref mut f) = out {
+            f.write_all(unpadded)?;
+            f.flush()?;
+        }
+
+        Ok(DecryptSummary {
+            major: self.header.major,
+            minor: self.header.minor,
+            data_length,
+            plain_length: processed_bytes + unpadded.len() as u64,
+            src_checksum: self.src_checksum,
+            calc_checksum: filehasher.finalize().into(),
+        })
+    }
+
+    /// Like [`decrypt_to`](Self::decrypt_to), but decrypts on up to `threads` threads.
+    pub fn decrypt_to_parallel<W: Write>(
+        &mut self,
+        out: Option<W>,
+        threads: NonZeroUsize,
+    ) -> Result<DecryptSummary>
+    where
+        R: Send,
+    {
+        self.decrypt_pipelined(out, threads)
+            .map(|(summary, _)| summary)
+    }
+
+    /// Decrypts the payload into `out` in a pipeline of threads.
+    ///
+    /// Reading, decrypting, hashing and writing run as separate stages, so
+    /// disk I/O overlaps with the crypto. Each CBC block only depends on the
+    /// ciphertext block before it, so batches are decrypted on up to `threads`
+    /// threads at once, each seeded with the ciphertext block preceding it;
+    /// plaintext is still hashed and written in order. Also returns how fast
+    /// each stage went.
+    pub fn decrypt_pipelined<W: Write>(
+        &mut self,
+        mut out: Option<W>,
+        threads: NonZeroUsize,
+    ) -> Result<(DecryptSummary, PipelineStats)>
+    where
+        R: Send,
+    {
+        let data_length = self.data_length;
+        self.reader.seek(SeekFrom::Start(DATA_OFFSET))?;
+        // everything but the padded last block
+        let bulk = pipeline::decrypt_bulk(
+            &mut self.reader,
+            &self.key,
+            self.header.iv,
+            data_length - 0x10,
+            out.as_mut(),
+            threads,
+        )?;
+        let mut filehasher = bulk.filehasher;
+
+        let mut last = SecretBuf::new(0x10);
+        self.reader.read_exact(&mut last)?;
+        let unpadded = PayloadDecryptor::new(&self.key, &bulk.iv).finish(&mut last)?;
+        filehasher.update(unpadded);
+        if let Some(ref mut f) = out {
+            f.write_all(unpadded)?;
+            f.flush()?;
+        }
+
+        let summary = DecryptSummary {
+            major: self.header.major,
+            minor: self.header.minor,
+            data_length,
+            plain_length: data_length - 0x10 + unpadded.len() as u64,
+            src_checksum: self.src_checksum,
+            calc_checksum: filehasher.finalize().into(),
+        };
+        Ok((summary, bulk.stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encrypt, plaintext, PASSWORD, SIZES};
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        for len in SIZES {
+            let plain = plaintext(len);
+            let mut out = Vec::new();
+            let summary = Decryptor::new(Cursor::new(encrypt(&plain)), PASSWORD)
+                .unwrap()
+                .decrypt_to(Some(&mut out))
+                .unwrap();
+            assert!(summary.is_match(), "length {}", len);
+            assert_eq!(summary.plain_length, len as u64);
+            assert_eq!(summary.data_length, (len as u64 / 16 + 1) * 16);
+            assert!(out == plain, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn round_trip_pipelined() {
+        for len in SIZES {
+            let plain = plaintext(len);
+            for threads in [1, 3] {
+                let mut out = Vec::new();
+                let (summary, _) = Decryptor::new(Cursor::new(encrypt(&plain)), PASSWORD)
+                    .unwrap()
+                    .decrypt_pipelined(Some(&mut out), NonZeroUsize::new(threads).unwrap())
+                    .unwrap();
+                assert!(summary.is_match(), "length {} on {} threads", len, threads);
+                assert!(out == plain, "length {} on {} threads", len, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_password() {
+        let file = encrypt(&plaintext(100));
+        assert!(matches!(
+            Decryptor::new(Cursor::new(file), b"not it"),
+            Err(EasyCrabError::WrongPassword)
+        ));
+    }
+
+    #[test]
+    fn truncated() {
+        let file = encrypt(&plaintext(100));
+        for len in [DATA_OFFSET as usize + 0x20, file.len() - 1] {
+            assert!(matches!(
+                Decryptor::new(Cursor::new(&file[..len]), PASSWORD),
+                Err(EasyCrabError::Truncated)
+            ));
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::prelude::*;
+
+use generic_array::GenericArray;
+
+use digest::Digest;
+use sha1::Sha1;
+use sha2::Sha512;
+
+use aes::{
+    cipher::{block_padding::Pkcs7, inout::InOutBuf, BlockEncryptMut, KeyIvInit},
+    Aes256Enc,
+};
+use cbc::Encryptor as CBCEncryptor;
+
+use crate::{read_full, EzcHeader, Result, SecretBuf, CHUNK_SIZE, MAGIC, RESERVED_LEN};
+
+/// Writes EasyCrypt V2.x files
+pub struct Encryptor {
+    header: EzcHeader,
+}
+
+impl Encryptor {
+    /// Creates an encrypter with a random IV and salt.
+    pub fn new(password: &[u8]) -> Result<Self> {
+        let mut iv = [0u8; 16];
+        let mut salt = [0u8; 16];
+        getrandom::getrandom(&mut iv).map_err(std::io::Error::from)?;
+        getrandom::getrandom(&mut salt).map_err(std::io::Error::from)?;
+        Ok(Self::with_iv_salt(password, iv, salt))
+    }
+
+    pub fn with_iv_salt(password: &[u8], iv: [u8; 16], salt: [u8; 16]) -> Self {
+        let mut keyhasher = Sha512::new();
+        keyhasher.update(password);
+        keyhasher.update(salt);
+        let mut header = EzcHeader {
+            magic: MAGIC,
+            major: 2,
+            minor: 0,
+            reserved: [0u8; RESERVED_LEN],
+            iv,
+            salt,
+            hash: [0u8; 64],
+        };
+        // straight into the header, which zeroizes it on drop
+        keyhasher.finalize_into(GenericArray::from_mut_slice(&mut header.hash));
+        Encryptor { header }
+    }
+
+    pub fn header(&self) -> &EzcHeader {
+        &self.header
+    }
+
+    fn cipher(&self) -> CBCEncryptor<Aes256Enc> {
+        CBCEncryptor::<Aes256Enc>::new(
+            GenericArray::from_slice(&self.header.key()),
+            &self.header.iv.into(),
+        )
+    }
+
+    /// Encrypts everything from `input` into `out`, returning the plaintext SHA-1.
+    pub fn encrypt_to<R: Read, W: Write>(&self, mut input: R, mut out: W) -> Result<[u8; 20]> {
+        self.header.write_to(&mut out)?;
+
+        let mut aes_cbc_enc = self.cipher();
+        // one spare block so the final chunk can always take its padding
+        let mut buf = SecretBuf::new(CHUNK_SIZE as usize + 0x10);
+        let mut filehasher = Sha1::new();
+
+        loop {
+            let n = read_full(&mut input, &mut buf[..CHUNK_SIZE as usize])?;
+            filehasher.update(&buf[..n]);
+            if n < CHUNK_SIZE as usize {
+                let ct = aes_cbc_enc
+                    .encrypt_padded_mut::<Pkcs7>(&mut buf, n)
+                    .expect("buffer has room for padding");
+                out.write_all(ct)?;
+                break;
+            }
+            let (blocks, _) = InOutBuf::from(&mut buf[..n]).into_chunks();
+            aes_cbc_enc.encrypt_blocks_inout_mut(blocks);
+            out.write_all(&buf[..n])?;
+        }
+
+        let checksum: [u8; 20] = filehasher.finalize().into();
+        let mut trailer = [0u8; 0x20];
+        trailer[..20].copy_from_slice(&checksum);
+        let trailer = self
+            .cipher()
+            .encrypt_padded_mut::<Pkcs7>(&mut trailer, 20)
+            .expect("buffer has room for padding");
+        out.write_all(trailer)?;
+        out.flush()?;
+        Ok(checksum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hexstring, Decryptor, DATA_OFFSET, IV_OFFSET};
+    use std::io::Cursor;
+
+    // Computed with Python's `cryptography` package from the format
+    // description. No file written by EasyCrypt itself is available yet;
+    // once one is, it should be checked in and decrypted here as well.
+    const PLAINTEXT: &[u8] = b"EasyCrypt known-answer test\n";
+    const PASSWORD: &[u8] = b"secret";
+    const HASH: &str = "e75b6b8740b126aa44a206e23923aa5a851aaad22cbb57e2b31127a63953cd2a\
+                        75f5f56c5b17adfac9270e701eef075099a4375d60fd8401d53a78c7fdc7b516";
+    const PAYLOAD_AND_TRAILER: &str =
+        "905d9b8addad5bc39a0927a2333c1ca12d1ad7d900c582ebd891cd8a4c8860a0\
+         70ef1125a47ffd7b364fcccff6febfd8b25f6661b8272e787af486d5dc892ea4";
+    const CHECKSUM: &str = "ed44825b69d708bf3eec7316cdeb94a0cd1c5168";
+
+    fn known_answer_file() -> Vec<u8> {
+        let iv = std::array::from_fn(|i| i as u8);
+        let salt = std::array::from_fn(|i| 16 + i as u8);
+        let mut out = Vec::new();
+        let checksum = Encryptor::with_iv_salt(PASSWORD, iv, salt)
+            .encrypt_to(PLAINTEXT, &mut out)
+            .unwrap();
+        assert_eq!(hexstring(&checksum), CHECKSUM);
+        out
+    }
+
+    #[test]
+    fn known_answer() {
+        let out = known_answer_file();
+        assert_eq!(&out[..5], b"EZC\x02\x00");
+        assert!(out[5..IV_OFFSET as usize].iter().all(|&c| c == 0));
+        // IV 00..0f followed by salt 10..1f
+        assert!(out[IV_OFFSET as usize..][..32].iter().copied().eq(0..32));
+        assert_eq!(
+            hexstring(&out[IV_OFFSET as usize + 32..DATA_OFFSET as usize]),
+            HASH
+        );
+        assert_eq!(hexstring(&out[DATA_OFFSET as usize..]), PAYLOAD_AND_TRAILER);
+    }
+
+    #[test]
+    fn known_answer_decrypts() {
+        let mut plain = Vec::new();
+        let summary = Decryptor::new(Cursor::new(known_answer_file()), PASSWORD)
+            .unwrap()
+            .decrypt_to(Some(&mut plain))
+            .unwrap();
+        assert!(summary.is_match());
+        assert_eq!(plain, PLAINTEXT);
+    }
+}
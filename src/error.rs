@@ -0,0 +1,157 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can go wrong while processing an EasyCrypt file
+///
+/// Each variant maps to a stable process exit code, see [`EasyCrabError::exit_code`].
+#[derive(Debug)]
+pub enum EasyCrabError {
+    /// Input path is missing or not a regular file
+    NotAFile(PathBuf),
+    /// Input does not start with the "EZC" magic
+    BadMagic,
+    /// Major/minor version this tool cannot handle
+    UnsupportedVersion(u8, u8),
+    /// Password does not match the stored SHA-512 verifier
+    WrongPassword,
+    /// Input ends before the header, payload or trailer is complete
+    Truncated,
+    /// PKCS#7 padding of the payload or trailer is invalid
+    BadPadding,
+    /// Stored SHA-1 of the plaintext differs from the calculated one
+    ChecksumMismatch,
+    /// Any other I/O failure
+    Io(io::Error),
+    /// Output path already exists and overwriting was not allowed
+    DestinationExists(PathBuf),
+    /// No candidate matched the stored SHA-512 verifier
+    PasswordNotFound,
+    /// Output path would overwrite the input itself
+    DestinationIsSource(PathBuf),
+    /// Password could not be obtained from the requested source
+    PasswordUnavailable(String),
+    /// Requested plaintext offset lies past the end, with the plaintext length
+    OffsetOutOfRange(u64, u64),
+}
+
+pub type Result<T> = std::result::Result<T, EasyCrabError>;
+
+impl EasyCrabError {
+    /// Process exit code for this error
+    ///
+    /// | Code | Variant               |
+    /// |------|-----------------------|
+    /// | 3    | `NotAFile`            |
+    /// | 4    | `BadMagic`            |
+    /// | 5    | `UnsupportedVersion`  |
+    /// | 6    | `WrongPassword`       |
+    /// | 7    | `Truncated`           |
+    /// | 8    | `BadPadding`          |
+    /// | 9    | `ChecksumMismatch`    |
+    /// | 10   | `Io`                  |
+    /// | 11   | `DestinationExists`   |
+    /// | 12   | `PasswordNotFound`    |
+    /// | 13   | `DestinationIsSource` |
+    /// | 14   | `PasswordUnavailable` |
+    /// | 15   | `OffsetOutOfRange`    |
+    ///
+    /// 1 and 2 are left to panics and usage errors respectively.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            EasyCrabError::NotAFile(_) => 3,
+            EasyCrabError::BadMagic => 4,
+            EasyCrabError::UnsupportedVersion(..) => 5,
+            EasyCrabError::WrongPassword => 6,
+            EasyCrabError::Truncated => 7,
+            EasyCrabError::BadPadding => 8,
+            EasyCrabError::ChecksumMismatch => 9,
+            EasyCrabError::Io(_) => 10,
+            EasyCrabError::DestinationExists(_) => 11,
+            EasyCrabError::PasswordNotFound => 12,
+            EasyCrabError::DestinationIsSource(_) => 13,
+            EasyCrabError::PasswordUnavailable(_) => 14,
+            EasyCrabError::OffsetOutOfRange(..) => 15,
+        }
+    }
+
+    /// Short, stable name of the variant for machine-readable output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EasyCrabError::NotAFile(_) => "not-a-file",
+            EasyCrabError::BadMagic => "bad-magic",
+            EasyCrabError::UnsupportedVersion(..) => "unsupported-version",
+            EasyCrabError::WrongPassword => "wrong-password",
+            EasyCrabError::Truncated => "truncated",
+            EasyCrabError::BadPadding => "bad-padding",
+            EasyCrabError::ChecksumMismatch => "checksum-mismatch",
+            EasyCrabError::Io(_) => "io",
+            EasyCrabError::DestinationExists(_) => "destination-exists",
+            EasyCrabError::PasswordNotFound => "password-not-found",
+            EasyCrabError::DestinationIsSource(_) => "destination-is-source",
+            EasyCrabError::PasswordUnavailable(_) => "password-unavailable",
+            EasyCrabError::OffsetOutOfRange(..) => "offset-out-of-range",
+        }
+    }
+}
+
+impl fmt::Display for EasyCrabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EasyCrabError::NotAFile(path) => write!(f, "{} is not a file", path.display()),
+            EasyCrabError::BadMagic => write!(f, "File is not EasyCrypt file"),
+            EasyCrabError::UnsupportedVersion(major, minor) => {
+                write!(f, "Unsupported EasyCrypt version (V{}.{})", major, minor)
+            }
+            EasyCrabError::WrongPassword => write!(f, "Password is incorrect"),
+            EasyCrabError::Truncated => write!(f, "File is truncated"),
+            EasyCrabError::BadPadding => write!(f, "Invalid padding, file is corrupted"),
+            EasyCrabError::ChecksumMismatch => write!(f, "Checksum mismatch"),
+            EasyCrabError::Io(e) => write!(f, "I/O error: {}", e),
+            EasyCrabError::DestinationExists(path) => {
+                write!(f, "Destination file {} already exists", path.display())
+            }
+            EasyCrabError::PasswordNotFound => write!(f, "Password not found"),
+            EasyCrabError::DestinationIsSource(path) => {
+                write!(f, "Destination {} is the input file itself", path.display())
+            }
+            EasyCrabError::PasswordUnavailable(reason) => {
+                write!(f, "Cannot read password: {}", reason)
+            }
+            EasyCrabError::OffsetOutOfRange(offset, length) => write!(
+                f,
+                "Offset {} is past the end of the {}-byte plaintext",
+                offset, length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EasyCrabError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EasyCrabError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EasyCrabError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => EasyCrabError::Truncated,
+            _ => EasyCrabError::Io(e),
+        }
+    }
+}
+
+/// Lets errors pass through `Read` and `Write` implementations
+impl From<EasyCrabError> for io::Error {
+    fn from(e: EasyCrabError) -> Self {
+        match e {
+            EasyCrabError::Io(e) => e,
+            EasyCrabError::Truncated => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
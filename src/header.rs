@@ -0,0 +1,198 @@
+use std::fmt;
+use std::io::prelude::*;
+
+use generic_array::GenericArray;
+
+use digest::Digest;
+use sha2::Sha512;
+
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{EasyCrabError, PayloadDecryptor, Result, SecretBuf, IV_OFFSET};
+
+/// "EZC" magic at the start of every EasyCrypt file
+pub const MAGIC: [u8; 3] = [0x45, 0x5a, 0x43];
+
+/// Start of the area between the version bytes and the IV
+pub const RESERVED_OFFSET: u64 = 0x05;
+pub const RESERVED_LEN: usize = (IV_OFFSET - RESERVED_OFFSET) as usize;
+
+/// Fixed-size header of an EasyCrypt V2.x file
+#[derive(Clone)]
+pub struct EzcHeader {
+    pub magic: [u8; 3],
+    pub major: u8,
+    pub minor: u8,
+    /// Raw bytes from `RESERVED_OFFSET` up to `IV_OFFSET`
+    ///
+    /// Their layout has not been worked out: that needs files written by
+    /// EasyCrypt itself, and none are available. They are kept verbatim and
+    /// written back unchanged; only [`stored_name`](Self::stored_name)
+    /// guesses at their contents.
+    pub reserved: [u8; RESERVED_LEN],
+    pub iv: [u8; 16],
+    pub salt: [u8; 16],
+    /// SHA-512(password || salt), first half doubles as the AES-256 key
+    ///
+    /// Zeroized when the header is dropped.
+    pub hash: [u8; 64],
+}
+
+impl EzcHeader {
+    /// Reads the header from the start of `reader`, leaving it at `DATA_OFFSET`.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0u8; RESERVED_OFFSET as usize];
+        reader.read_exact(&mut magic)?;
+
+        // check "EZC" magic
+        if magic[..3] != MAGIC {
+            return Err(EasyCrabError::BadMagic);
+        }
+
+        if magic[3] != 2 {
+            return Err(EasyCrabError::UnsupportedVersion(magic[3], magic[4]));
+        }
+
+        let mut reserved = [0u8; RESERVED_LEN];
+        reader.read_exact(&mut reserved)?;
+
+        let mut iv = [0u8; 16];
+        reader.read_exact(&mut iv)?;
+
+        let mut salt = [0u8; 16];
+        reader.read_exact(&mut salt)?;
+
+        let mut hash = [0u8; 64];
+        reader.read_exact(&mut hash)?;
+
+        Ok(EzcHeader {
+            magic: [magic[0], magic[1], magic[2]],
+            major: magic[3],
+            minor: magic[4],
+            reserved,
+            iv,
+            salt,
+            hash,
+        })
+    }
+
+    /// Checks `password` against the stored SHA-512 verifier.
+    pub fn check_password(&self, password: &[u8]) -> bool {
+        let mut keyhasher = Sha512::new();
+        keyhasher.update(password);
+        keyhasher.update(self.salt);
+        let mut result = Zeroizing::new([0u8; 64]);
+        keyhasher.finalize_into(GenericArray::from_mut_slice(&mut result[..]));
+        self.hash == *result
+    }
+
+    /// AES-256 key, the first half of `hash`
+    pub fn key(&self) -> SecretBuf {
+        SecretBuf::from_slice(&self.hash[..32])
+    }
+
+    /// Best-effort guess at the original file name kept in `reserved`
+    ///
+    /// The layout of the reserved area is unconfirmed, so this takes the first
+    /// NUL-terminated run of non-zero bytes and accepts it only if it decodes as
+    /// UTF-16LE or UTF-8 without control characters. The result is reduced to a
+    /// bare file name that cannot escape the output directory.
+    pub fn stored_name(&self) -> Option<String> {
+        let start = self.reserved.iter().position(|&c| c != 0)?;
+        let rest = &self.reserved[start..];
+
+        let utf16: Vec<u16> = rest
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+        let utf8 = &rest[..rest.iter().position(|&c| c == 0).unwrap_or(rest.len())];
+
+        // read UTF-16LE only when the first character's high byte is zero,
+        // otherwise plain ASCII would decode as CJK
+        let name = if utf16.len() > 1 && rest.get(1) == Some(&0) {
+            String::from_utf16(&utf16).ok()
+        } else {
+            String::from_utf8(utf8.to_vec()).ok()
+        }?;
+        if name.chars().any(char::is_control) {
+            return None;
+        }
+        sanitize_file_name(&name)
+    }
+
+    /// Decrypts the 0x20-byte trailer into the stored plaintext SHA-1.
+    pub fn decrypt_trailer(&self, trailer: &[u8; 0x20]) -> Result<[u8; 20]> {
+        let mut trailer = *trailer;
+        PayloadDecryptor::new(&self.key(), &self.iv)
+            .finish(&mut trailer)?
+            .try_into()
+            .map_err(|_| EasyCrabError::BadPadding)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.magic)?;
+        writer.write_all(&[self.major, self.minor])?;
+        writer.write_all(&self.reserved)?;
+        writer.write_all(&self.iv)?;
+        writer.write_all(&self.salt)?;
+        writer.write_all(&self.hash)?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for EzcHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the hash holds the key, so it stays out of logs
+        f.debug_struct("EzcHeader")
+            .field("magic", &self.magic)
+            .field("major", &self.major)
+            .field("minor", &self.minor)
+            .field("reserved", &self.reserved)
+            .field("iv", &self.iv)
+            .field("salt", &self.salt)
+            .field("hash", &"<redacted>")
+            .finish()
+    }
+}
+
+impl Drop for EzcHeader {
+    fn drop(&mut self) {
+        self.hash.zeroize();
+    }
+}
+
+/// Reduces `name` to its last path component, rejecting anything that could
+/// point outside the directory it is joined to.
+fn sanitize_file_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?;
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encrypt, PASSWORD};
+
+    #[test]
+    fn debug_redacts_hash() {
+        let file = encrypt(b"");
+        let header = EzcHeader::read_from(&mut &file[..]).unwrap();
+        assert!(header.check_password(PASSWORD));
+        let debug = format!("{:?}", header);
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains(&format!("{:?}", header.hash)));
+        assert!(!debug.contains(&format!("{:?}", &header.hash[..32])));
+    }
+}
@@ -0,0 +1,48 @@
+use std::io::{prelude::*, SeekFrom};
+
+use crate::{EasyCrabError, EzcHeader, Result, DATA_OFFSET};
+
+/// Metadata that can be read from an EasyCrypt file without a password
+#[derive(Clone, Debug)]
+pub struct FileInfo {
+    pub header: EzcHeader,
+    pub file_length: u64,
+    /// Bytes between `DATA_OFFSET` and the trailer, padding included
+    pub data_length: u64,
+    /// SHA-1 of the plaintext from the trailer, if the trailer decrypts cleanly
+    pub src_checksum: Option<[u8; 20]>,
+}
+
+impl FileInfo {
+    pub fn read_from<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let header = EzcHeader::read_from(reader)?;
+        let file_length = reader.seek(SeekFrom::End(0))?;
+        let data_length = file_length.saturating_sub(DATA_OFFSET + 0x20);
+
+        let src_checksum = if data_length > 0 {
+            reader.seek(SeekFrom::End(-0x20))?;
+            let mut trailer = [0u8; 0x20];
+            reader.read_exact(&mut trailer)?;
+            match header.decrypt_trailer(&trailer) {
+                Ok(checksum) => Some(checksum),
+                Err(EasyCrabError::BadPadding) => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+
+        Ok(FileInfo {
+            header,
+            file_length,
+            data_length,
+            src_checksum,
+        })
+    }
+
+    /// Whether the payload is a whole number of AES blocks
+    pub fn is_aligned(&self) -> bool {
+        self.data_length > 0 && self.data_length.is_multiple_of(0x10)
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::Result;
+
+/// A file found while expanding command line inputs
+#[derive(Clone, Debug)]
+pub struct InputFile {
+    pub path: PathBuf,
+    /// Path below the directory or the literal part of the glob pattern it was
+    /// found in, or just the file name
+    pub relative: PathBuf,
+}
+
+impl InputFile {
+    fn new(path: PathBuf) -> Self {
+        let relative = path.file_name().map_or_else(|| path.clone(), PathBuf::from);
+        InputFile { path, relative }
+    }
+}
+
+/// Expands command line inputs into a list of files.
+///
+/// Paths that do not exist are treated as glob patterns. Directories yield the
+/// `.ezc` files they contain, descending into subdirectories when `recursive`
+/// is set. Anything else is passed through so the caller can report it.
+pub fn expand_inputs(inputs: &[PathBuf], recursive: bool) -> Result<Vec<InputFile>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            collect_dir(input, input, recursive, &mut files)?;
+        } else if input.exists() {
+            files.push(InputFile::new(input.clone()));
+        } else {
+            let pattern = input.to_string_lossy();
+            let root = literal_prefix(input);
+            let mut matched = false;
+            if let Ok(paths) = glob::glob(&pattern) {
+                for path in paths.flatten() {
+                    matched = true;
+                    if path.is_dir() {
+                        collect_dir(&root, &path, recursive, &mut files)?;
+                    } else {
+                        let relative = path.strip_prefix(&root).unwrap_or(&path).to_path_buf();
+                        files.push(InputFile { path, relative });
+                    }
+                }
+            }
+            if !matched {
+                files.push(InputFile::new(input.clone()));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Leading components of a glob pattern that contain no wildcards
+fn literal_prefix(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .collect()
+}
+
+fn collect_dir(root: &Path, dir: &Path, recursive: bool, files: &mut Vec<InputFile>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            if recursive {
+                collect_dir(root, &path, recursive, files)?;
+            }
+        } else if path.extension().is_some_and(|ext| ext == "ezc") {
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            files.push(InputFile { path, relative });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Cross-platform EasyCrypt V2.x decrypter and encrypter.
+
+mod atomic;
+mod batch;
+mod crack;
+mod decrypt;
+mod encrypt;
+mod error;
+mod header;
+mod info;
+mod inputs;
+mod password;
+mod payload;
+mod pipeline;
+mod reader;
+mod salvage;
+mod secret;
+mod stream;
+
+pub use atomic::AtomicFile;
+pub use batch::run_ordered;
+pub use crack::{apply_rules, find_password, Mask, MaskCandidates, Rule};
+pub use decrypt::{DecryptSummary, Decryptor};
+pub use encrypt::Encryptor;
+pub use error::{EasyCrabError, Result};
+pub use header::{EzcHeader, MAGIC, RESERVED_LEN, RESERVED_OFFSET};
+pub use info::FileInfo;
+pub use inputs::{expand_inputs, InputFile};
+#[cfg(unix)]
+pub use password::read_password_fd;
+pub use password::{
+    prompt_password, read_password_env, read_password_file, read_password_list, Password,
+    MAX_PASSWORD_LEN,
+};
+pub use payload::PayloadDecryptor;
+pub use pipeline::{PipelineStats, StageStats};
+pub use reader::EzcReader;
+pub use salvage::{Damage, SalvageReport, Salvager};
+pub use secret::SecretBuf;
+pub use stream::StreamDecryptor;
+
+pub const IV_OFFSET: u64 = 0x43;
+pub const DATA_OFFSET: u64 = 0xA3;
+pub const CHUNK_SIZE: u64 = 0x8000;
+
+pub fn hexstring(arr: &[u8]) -> String {
+    use std::fmt::Write;
+    let mut s = String::with_capacity(arr.len() * 2);
+    for c in arr {
+        write!(&mut s, "{:02x}", c).unwrap();
+    }
+    s
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning the byte count.
+pub(crate) fn read_full<R: std::io::Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+pub(crate) mod test_util {
+    use crate::{Encryptor, CHUNK_SIZE};
+
+    pub const PASSWORD: &[u8] = b"pw";
+
+    const CHUNK: usize = CHUNK_SIZE as usize;
+
+    /// Plaintext lengths around the block and chunk boundaries
+    pub const SIZES: [usize; 11] = [
+        0,
+        1,
+        15,
+        16,
+        17,
+        CHUNK - 16,
+        CHUNK - 1,
+        CHUNK,
+        CHUNK + 1,
+        CHUNK + 16,
+        3 * CHUNK,
+    ];
+
+    /// Plaintext that does not repeat with the block size
+    pub fn plaintext(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Encrypts `plain` into a complete EasyCrypt file under [`PASSWORD`].
+    pub fn encrypt(plain: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        Encryptor::with_iv_salt(PASSWORD, [7; 16], [9; 16])
+            .encrypt_to(plain, &mut out)
+            .unwrap();
+        out
+    }
+}
@@ -1,145 +1,1363 @@
-use clap::Parser;
+use clap::{error::ErrorKind, ArgGroup, Args, CommandFactory, Parser, Subcommand, ValueEnum};
 
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{prelude::*, BufReader, BufWriter, SeekFrom};
+use std::io::{self, prelude::*, BufReader, BufWriter, IsTerminal, SeekFrom};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
 
-use generic_array::{typenum::U16, GenericArray};
+use serde_json::json;
+use zeroize::Zeroize;
 
-use digest::Digest;
-use sha1::Sha1;
-use sha2::Sha512;
-
-use aes::{
-    cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit},
-    Aes256Dec,
+#[cfg(unix)]
+use easycrab::read_password_fd;
+use easycrab::{
+    apply_rules, expand_inputs, find_password, hexstring, run_ordered, AtomicFile, DecryptSummary,
+    Decryptor, EasyCrabError, Encryptor, EzcHeader, EzcReader, FileInfo, InputFile, Mask,
+    PipelineStats, Result, Rule, SalvageReport, Salvager, StageStats, StreamDecryptor,
+    RESERVED_OFFSET,
+};
+use easycrab::{
+    prompt_password, read_password_env, read_password_file, read_password_list, Password,
 };
-use cbc::Decryptor as CBCDecryptor;
+
+/// Path standing for stdin or stdout
+const STDIO: &str = "-";
+
+/// Prints a status line to stdout, or to stderr when stdout carries plaintext
+macro_rules! status {
+    ($to_stderr:expr, $($arg:tt)*) => {
+        if $to_stderr {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
-struct Args {
+struct Cli {
+    #[arg(long, global = true, help = "Print one JSON object per file")]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Rewrites the `easycrab [-f] [--no-write] FILE [PASSWORD]` form from before
+/// subcommands into a `decrypt` command line.
+///
+/// Only applies when the first positional argument is not a subcommand, so
+/// global flags such as `--json` keep working in front of one. The password
+/// is moved over as it is rather than copied into a new string.
+fn legacy_argv(argv: Vec<OsString>) -> Vec<OsString> {
+    let is_flag = |arg: &OsString| arg.len() > 1 && arg.to_string_lossy().starts_with('-');
+    let first = argv
+        .iter()
+        .skip(1)
+        .find(|arg| *arg == "--" || !is_flag(arg));
+    match first {
+        None => return argv,
+        Some(name) if name != "--" => {
+            let name = name.to_string_lossy();
+            if name == "help" || Cli::command().find_subcommand(name.as_ref()).is_some() {
+                return argv;
+            }
+        }
+        Some(_) => {}
+    }
+
+    let mut argv = argv.into_iter();
+    let mut rewritten = vec![
+        argv.next().unwrap_or_else(|| "easycrab".into()),
+        "decrypt".into(),
+    ];
+    let mut positionals = Vec::new();
+    let mut only_positionals = false;
+    for arg in argv {
+        if only_positionals || !is_flag(&arg) {
+            positionals.push(arg);
+        } else if arg == "--" {
+            only_positionals = true;
+        } else {
+            rewritten.push(arg);
+        }
+    }
+    let mut positionals = positionals.into_iter();
+    let file = positionals.next();
+    if let Some(password) = positionals.next() {
+        rewritten.push("--password".into());
+        rewritten.push(password);
+    }
+    rewritten.push("--".into());
+    rewritten.extend(file);
+    rewritten.extend(positionals);
+    rewritten
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Decrypt EasyCrypt files
+    Decrypt(DecryptArgs),
+    /// Encrypt a file into EasyCrypt V2.x format
+    Encrypt(EncryptArgs),
+    /// Show file metadata without a password
+    #[command(visible_alias = "inspect")]
+    Info(InfoArgs),
+    /// Check password and integrity without writing plaintext
+    Verify(VerifyArgs),
+    /// Decrypt only a byte range of a file
+    Extract(ExtractArgs),
+    /// Recover the password from a wordlist or mask
+    Crack(CrackArgs),
+}
+
+#[derive(Args)]
+struct DecryptArgs {
     #[arg(short, long, help = "Allow file overwrite")]
     force: bool,
     #[arg(long, help = "Don't write file")]
     no_write: bool,
-    #[arg(long, hide = true)]
-    override_password: bool,
-    file: std::path::PathBuf,
-    #[arg(required_unless_present = "override_password")]
+    #[arg(
+        long,
+        value_enum,
+        help = "What a checksum mismatch does [default: warn on a terminal, fail otherwise]"
+    )]
+    on_mismatch: Option<MismatchPolicy>,
+    #[arg(long, help = "Keep the output even if its checksum does not match")]
+    keep_on_mismatch: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["on_mismatch", "keep_on_mismatch"],
+        help = "Recover what is left of a truncated or damaged file"
+    )]
+    salvage: bool,
+    #[arg(short, long, help = "Descend into subdirectories")]
+    recursive: bool,
+    #[arg(
+        short,
+        long,
+        default_value_t = NonZeroUsize::MIN,
+        help = "Decrypt this many files at once"
+    )]
+    jobs: NonZeroUsize,
+    #[arg(
+        long,
+        default_value_t = NonZeroUsize::MIN,
+        help = "Decrypt each file on this many threads"
+    )]
+    threads: NonZeroUsize,
+    #[arg(short, long, help = "Report the throughput of each pipeline stage")]
+    verbose: bool,
+    #[command(flatten)]
+    secret: PasswordArgs,
+    #[arg(short, long, help = "Write plaintext here, - for stdout")]
+    output: Option<PathBuf>,
+    #[arg(
+        long,
+        conflicts_with = "output",
+        help = "Write plaintext into this directory, mirroring input directories"
+    )]
+    output_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        conflicts_with = "output",
+        help = "Name output after the file name stored in the header, if any"
+    )]
+    use_stored_name: bool,
+    #[arg(
+        required = true,
+        help = "Files, directories or glob patterns, - for stdin"
+    )]
+    files: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct PasswordArgs {
+    #[arg(
+        long,
+        alias = "override-password",
+        conflicts_with_all = ["password", "password_source", "password_list"],
+        help = "Use the key stored in the header, no password needed",
+        long_help = "Use the key stored in the header, no password needed\n\n\
+            EasyCrypt V2.x stores SHA-512(password || salt) in the header and uses its \
+            first 32 bytes as the AES-256 key, so any file can be decrypted without \
+            knowing the password."
+    )]
+    no_password: bool,
+    #[arg(
+        short,
+        long,
+        conflicts_with = "password_source",
+        allow_hyphen_values = true,
+        help = "Password used to encrypt the files [default: prompt]"
+    )]
     password: Option<String>,
+    #[command(flatten)]
+    source: PasswordSource,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["password", "password_source"],
+        help = "Try each line of a file as password, using the first that matches"
+    )]
+    password_list: Option<PathBuf>,
+}
+
+/// Ways to pass a password without it showing up in `ps` or shell history
+#[derive(Args)]
+#[group(id = "password_source", multiple = false)]
+struct PasswordSource {
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Read the password from the first line of a file"
+    )]
+    password_file: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "VAR",
+        help = "Read the password from an environment variable"
+    )]
+    password_env: Option<String>,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Read the password from an open file descriptor"
+    )]
+    password_fd: Option<i32>,
+}
+
+impl PasswordSource {
+    /// Reads the password from the given source, prompting if there is none.
+    fn read(&self, confirm: bool) -> Result<Password> {
+        if let Some(path) = &self.password_file {
+            return read_password_file(path);
+        }
+        if let Some(name) = &self.password_env {
+            return read_password_env(name);
+        }
+        if let Some(fd) = self.password_fd {
+            #[cfg(unix)]
+            return read_password_fd(fd);
+            #[cfg(not(unix))]
+            return Err(EasyCrabError::PasswordUnavailable(format!(
+                "file descriptor {} is not supported on this platform",
+                fd
+            )));
+        }
+        let password = prompt_password("Password: ")?;
+        if confirm && prompt_password("Confirm password: ")? != password {
+            return Err(EasyCrabError::PasswordUnavailable(
+                "passwords do not match".to_string(),
+            ));
+        }
+        Ok(password)
+    }
 }
 
-const IV_OFFSET: u64 = 0x43;
-const DATA_OFFSET: u64 = 0xA3;
-const CHUNK_SIZE: u64 = 0x8000;
+impl PasswordArgs {
+    /// Takes the password out of the arguments, wiping the command line copy.
+    fn resolve(&mut self) -> Result<Secret> {
+        if self.no_password {
+            return Ok(Secret::HeaderKey);
+        }
+        if let Some(path) = &self.password_list {
+            return read_password_list(path).map(Secret::List);
+        }
+        match self.password.take() {
+            Some(password) => Ok(Secret::Password(take_password(password))),
+            None => self.source.read(false).map(Secret::Password),
+        }
+    }
+}
+
+/// Moves a password given as an argument into a [`Password`].
+fn take_password(mut password: String) -> Password {
+    let secret = Password::from_slice(password.as_bytes());
+    password.zeroize();
+    secret
+}
+
+/// What a file is decrypted with
+enum Secret {
+    /// The key stored in the header
+    HeaderKey,
+    Password(Password),
+    /// Candidates from `--password-list`, in file order
+    List(Vec<Password>),
+}
 
-fn hexstring(arr: &[u8]) -> String {
-    use std::fmt::Write;
-    let mut s = String::with_capacity(arr.len() * 2);
-    for c in arr {
-        write!(&mut s, "{:02x}", c).unwrap();
+impl Secret {
+    /// Checks the header's verifier, returning the matching `--password-list` line.
+    fn check(&self, header: &EzcHeader) -> Result<Option<usize>> {
+        match self {
+            Secret::HeaderKey => Ok(None),
+            Secret::Password(password) if header.check_password(password) => Ok(None),
+            Secret::Password(_) => Err(EasyCrabError::WrongPassword),
+            Secret::List(list) => list
+                .iter()
+                .position(|password| header.check_password(password))
+                .map(|i| Some(i + 1))
+                .ok_or(EasyCrabError::WrongPassword),
+        }
     }
-    s
 }
 
-fn main() {
-    let args = Args::parse();
-    if !args.file.is_file() {
-        panic!("Path is not file")
+/// Result of decrypting or verifying one file
+struct Decrypted {
+    summary: DecryptSummary,
+    /// Line of the `--password-list` entry that matched
+    password_line: Option<usize>,
+    /// Pipeline throughput, with `--verbose`
+    stages: Option<PipelineStats>,
+    /// Output was dropped because its checksum did not match
+    discarded: bool,
+}
+
+/// Suffix for status lines naming the matching `--password-list` entry
+fn password_note(password_line: Option<usize>) -> String {
+    match password_line {
+        Some(line) => format!(" (password list line {})", line),
+        None => String::new(),
     }
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    #[arg(short, long, help = "Descend into subdirectories")]
+    recursive: bool,
+    #[arg(
+        short,
+        long,
+        default_value_t = NonZeroUsize::MIN,
+        help = "Verify this many files at once"
+    )]
+    jobs: NonZeroUsize,
+    #[arg(
+        long,
+        default_value_t = NonZeroUsize::MIN,
+        help = "Decrypt each file on this many threads"
+    )]
+    threads: NonZeroUsize,
+    #[arg(short, long, help = "Report the throughput of each pipeline stage")]
+    verbose: bool,
+    #[command(flatten)]
+    secret: PasswordArgs,
+    #[arg(
+        required = true,
+        help = "Files, directories or glob patterns, - for stdin"
+    )]
+    files: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    #[arg(short, long, help = "Allow file overwrite")]
+    force: bool,
+    #[arg(long, default_value_t = 0, help = "First plaintext byte to extract")]
+    offset: u64,
+    #[arg(long, help = "Number of bytes to extract [default: up to the end]")]
+    length: Option<u64>,
+    #[command(flatten)]
+    secret: PasswordArgs,
+    #[arg(
+        short,
+        long,
+        default_value = STDIO,
+        help = "Write the range here, - for stdout"
+    )]
+    output: PathBuf,
+    file: PathBuf,
+}
+
+#[derive(Args)]
+struct EncryptArgs {
+    #[arg(short, long, help = "Allow file overwrite")]
+    force: bool,
+    #[arg(
+        short,
+        long,
+        conflicts_with = "password_source",
+        allow_hyphen_values = true,
+        help = "Password to encrypt with [default: prompt]"
+    )]
+    password: Option<String>,
+    #[command(flatten)]
+    source: PasswordSource,
+    file: PathBuf,
+}
+
+#[derive(Args)]
+struct InfoArgs {
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("source").required(true).multiple(true).args(["wordlist", "mask"])))]
+struct CrackArgs {
+    #[arg(short, long, help = "File with one candidate password per line")]
+    wordlist: Option<PathBuf>,
+    #[arg(
+        short = 'r',
+        long = "rule",
+        value_name = "RULE",
+        value_parser = Rule::from_str,
+        help = "Also try wordlist entries transformed by capitalize, upper, reverse or append-digit"
+    )]
+    rules: Vec<Rule>,
+    #[arg(
+        short,
+        long,
+        value_parser = Mask::from_str,
+        help = "Try every password matching a mask such as ?u?l?l?l?d?d"
+    )]
+    mask: Option<Mask>,
+    #[arg(
+        short,
+        long,
+        default_value_t = thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+        help = "Number of worker threads"
+    )]
+    jobs: NonZeroUsize,
+    file: PathBuf,
+}
 
-    let new_file_name = args.file.with_extension("");
-    if !args.no_write & new_file_name.exists() & !args.force {
-        panic!("Destination file already exists")
+fn main() -> ExitCode {
+    let cli = Cli::parse_from(legacy_argv(std::env::args_os().collect()));
+    let json = cli.json;
+    let (file, result) = match cli.command {
+        Command::Decrypt(args) => return decrypt(args, json),
+        Command::Encrypt(args) => (args.file.clone(), encrypt(args, json)),
+        Command::Info(args) => return info(args, json),
+        Command::Verify(args) => return verify(args, json),
+        Command::Extract(args) => (args.file.clone(), extract(args, json)),
+        Command::Crack(args) => (args.file.clone(), crack(args, json)),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if json {
+                println!("{}", error_json(&file, &e));
+            } else {
+                eprintln!("Error: {}", e);
+            }
+            ExitCode::from(e.exit_code())
+        }
     }
+}
 
-    let mut enc_file = BufReader::new(File::open(args.file).unwrap());
+/// JSON record for a file that could not be processed
+fn error_json(file: &Path, e: &EasyCrabError) -> serde_json::Value {
+    json!({
+        "file": file.to_string_lossy(),
+        "status": "error",
+        "error": e.kind(),
+        "message": e.to_string(),
+    })
+}
+
+/// Reports an error that is not tied to a single file
+fn report_error(e: &EasyCrabError, json: bool) {
+    if json {
+        println!(
+            "{}",
+            json!({ "status": "error", "error": e.kind(), "message": e.to_string() })
+        );
+    } else {
+        eprintln!("Error: {}", e);
+    }
+}
 
-    let mut magic = [0u8; 7];
-    enc_file.read_exact(&mut magic).unwrap();
+/// JSON record for a decrypted or verified file
+fn summary_json(file: &Path, result: &Result<Decrypted>) -> serde_json::Value {
+    let (summary, password_line) = match result {
+        Ok(decrypted) => (&decrypted.summary, decrypted.password_line),
+        Err(e) => return error_json(file, e),
+    };
+    let mut record = json!({
+        "file": file.to_string_lossy(),
+        "status": if summary.is_match() { "ok" } else { "checksum-mismatch" },
+        "version": format!("{}.{}", summary.major, summary.minor),
+        "data_length": summary.data_length,
+        "plain_length": summary.plain_length,
+        "src_checksum": hexstring(&summary.src_checksum),
+        "calc_checksum": hexstring(&summary.calc_checksum),
+    });
+    if let Some(line) = password_line {
+        record["password_line"] = line.into();
+    }
+    if result.as_ref().is_ok_and(|d| d.discarded) {
+        record["output_discarded"] = true.into();
+    }
+    if let Some(stages) = result.as_ref().ok().and_then(|d| d.stages.as_ref()) {
+        record["stages"] = stages_json(stages);
+    }
+    record
+}
 
-    // check "EZC" magic
-    if magic[..3] != [0x45, 0x5a, 0x43] {
-        panic!("File is not EasyCrypt file")
+/// JSON record for a salvaged file
+fn salvage_json(file: &Path, result: &Result<Salvaged>) -> serde_json::Value {
+    let salvaged = match result {
+        Ok(salvaged) => salvaged,
+        Err(e) => return error_json(file, e),
+    };
+    let report = &salvaged.report;
+    let mut record = json!({
+        "file": file.to_string_lossy(),
+        "status": if report.damage.is_some() { "salvaged" } else { "ok" },
+        "version": format!("{}.{}", report.major, report.minor),
+        "recovered": report.recovered,
+        "calc_checksum": hexstring(&report.calc_checksum),
+    });
+    if let Some(src_checksum) = &report.src_checksum {
+        record["src_checksum"] = hexstring(src_checksum).into();
+    }
+    if let Some(damage) = &report.damage {
+        record["damage"] = damage.error().kind().into();
+        if let Some(offset) = damage.offset() {
+            record["damage_offset"] = offset.into();
+        }
     }
+    if let Some(line) = salvaged.password_line {
+        record["password_line"] = line.into();
+    }
+    if let Some(stages) = &salvaged.stages {
+        record["stages"] = stages_json(stages);
+    }
+    record
+}
 
-    if magic[3] != 2 {
-        panic!("Unsupported EasyCrypt version (V{}.{})", magic[3], magic[4]);
+fn stages_json(stages: &PipelineStats) -> serde_json::Value {
+    let stage =
+        |stats: &StageStats| json!({ "bytes": stats.bytes, "seconds": stats.busy.as_secs_f64() });
+    json!({
+        "read": stage(&stages.read),
+        "decrypt": stage(&stages.decrypt),
+        "hash": stage(&stages.hash),
+        "write": stage(&stages.write),
+        "threads": stages.threads,
+        "seconds": stages.elapsed.as_secs_f64(),
+    })
+}
+
+/// How a checksum mismatch is handled
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum MismatchPolicy {
+    /// Report it but exit successfully
+    Warn,
+    /// Exit with an error
+    Fail,
+    /// Exit with an error and discard the output, even with --keep-on-mismatch
+    Delete,
+}
+
+impl DecryptArgs {
+    fn keep_on_mismatch(&self) -> bool {
+        self.keep_on_mismatch && self.on_mismatch != Some(MismatchPolicy::Delete)
     }
+}
 
-    println!("Decrypting Easycrypt V{}.{} file...", magic[3], magic[4]);
+/// Where decrypted plaintext goes
+enum Output {
+    Stdout(BufWriter<io::StdoutLock<'static>>),
+    File(AtomicFile),
+}
 
-    enc_file.seek(SeekFrom::Start(IV_OFFSET)).unwrap();
-    let mut iv = [0u8; 16];
-    enc_file.read_exact(&mut iv).unwrap();
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(w) => w.write(buf),
+            Output::File(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(w) => w.flush(),
+            Output::File(w) => w.flush(),
+        }
+    }
+}
 
-    let mut salt = [0u8; 16];
-    enc_file.read_exact(&mut salt).unwrap();
+#[derive(Default)]
+struct BatchStats {
+    ok: usize,
+    wrong_password: usize,
+    checksum_mismatch: usize,
+    failed: usize,
+}
 
-    let mut hash = [0u8; 64];
-    enc_file.read_exact(&mut hash).unwrap();
+fn decrypt(mut args: DecryptArgs, json: bool) -> ExitCode {
+    let policy = *args
+        .on_mismatch
+        .get_or_insert(if io::stdout().is_terminal() {
+            MismatchPolicy::Warn
+        } else {
+            MismatchPolicy::Fail
+        });
 
-    if !args.override_password {
-        let mut keyhasher = Sha512::new();
-        keyhasher.update(args.password.unwrap());
-        keyhasher.update(salt);
-        let result = keyhasher.finalize();
-        if GenericArray::from_slice(&hash) != &result {
-            panic!("Password is incorrect")
+    let files = match expand_inputs(&args.files, args.recursive) {
+        Ok(files) => files,
+        Err(e) => {
+            report_error(&e, json);
+            return ExitCode::from(e.exit_code());
         }
+    };
+    if args.output.is_some() && files.len() > 1 {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--output can only be used with a single input file",
+            )
+            .exit();
+    }
+    if !args.no_write {
+        // one output silently replacing another is worse than refusing up front
+        let mut outputs = HashMap::new();
+        for file in &files {
+            // unreadable headers fall back to the default name and fail later
+            let stored_name = stored_name(file, &args).ok().flatten();
+            let path = output_path(file, &args, stored_name.as_deref());
+            if path == Path::new(STDIO) {
+                continue;
+            }
+            if let Some(other) = outputs.insert(path.clone(), &file.path) {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!(
+                            "{} and {} would both be decrypted to {}",
+                            other.display(),
+                            file.path.display(),
+                            path.display()
+                        ),
+                    )
+                    .exit();
+            }
+        }
+    }
+    let secret = match args.secret.resolve() {
+        Ok(secret) => secret,
+        Err(e) => {
+            report_error(&e, json);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+
+    let verbose = files.len() == 1 && !json;
+    // keep stdout clean when it carries plaintext
+    let to_stderr = !args.no_write
+        && files
+            .iter()
+            .any(|f| output_path(f, &args, None) == Path::new(STDIO));
+    if args.salvage {
+        return salvage(&files, &args, &secret, json, to_stderr);
+    }
+    let mut stats = BatchStats::default();
+    let mut exit_code = ExitCode::SUCCESS;
+    run_ordered(
+        &files,
+        args.jobs,
+        |file| decrypt_file(file, &args, &secret, verbose, to_stderr),
+        |file, result| {
+            if json {
+                status!(to_stderr, "{}", summary_json(&file.path, &result));
+            } else if let Ok(Decrypted {
+                stages: Some(stages),
+                ..
+            }) = &result
+            {
+                status!(to_stderr, "{}: {}", file.path.display(), stages);
+            }
+            match result {
+                Ok(decrypted) if decrypted.summary.is_match() => {
+                    if !json {
+                        status!(
+                            to_stderr,
+                            "{}: OK{}",
+                            file.path.display(),
+                            password_note(decrypted.password_line)
+                        );
+                    }
+                    stats.ok += 1;
+                }
+                Ok(decrypted) => {
+                    if !json {
+                        status!(
+                            to_stderr,
+                            "{}: checksum mismatch{}{}",
+                            file.path.display(),
+                            password_note(decrypted.password_line),
+                            if decrypted.discarded {
+                                ", output discarded"
+                            } else {
+                                ""
+                            }
+                        );
+                    }
+                    stats.checksum_mismatch += 1;
+                    if policy != MismatchPolicy::Warn {
+                        exit_code = ExitCode::from(EasyCrabError::ChecksumMismatch.exit_code());
+                    }
+                }
+                Err(e) => {
+                    if !json {
+                        eprintln!("Error: {}: {}", file.path.display(), e);
+                    }
+                    match e {
+                        EasyCrabError::WrongPassword => stats.wrong_password += 1,
+                        _ => stats.failed += 1,
+                    }
+                    exit_code = ExitCode::from(e.exit_code());
+                }
+            }
+        },
+    );
+
+    if files.len() > 1 && !json {
+        status!(
+            to_stderr,
+            "{} files: {} decrypted, {} wrong password, {} checksum mismatch, {} failed",
+            files.len(),
+            stats.ok,
+            stats.wrong_password,
+            stats.checksum_mismatch,
+            stats.failed
+        );
+    }
+    exit_code
+}
+
+/// Outcome of salvaging one file
+struct Salvaged {
+    report: SalvageReport,
+    password_line: Option<usize>,
+    stages: Option<PipelineStats>,
+}
+
+fn salvage(
+    files: &[InputFile],
+    args: &DecryptArgs,
+    secret: &Secret,
+    json: bool,
+    to_stderr: bool,
+) -> ExitCode {
+    let (mut intact, mut salvaged, mut failed) = (0, 0, 0);
+    let mut exit_code = ExitCode::SUCCESS;
+    run_ordered(
+        files,
+        args.jobs,
+        |file| salvage_file(file, args, secret),
+        |file, result| {
+            if json {
+                status!(to_stderr, "{}", salvage_json(&file.path, &result));
+            } else if let Ok(Salvaged {
+                stages: Some(stages),
+                ..
+            }) = &result
+            {
+                status!(to_stderr, "{}: {}", file.path.display(), stages);
+            }
+            match result {
+                Ok(Salvaged { report, .. }) => {
+                    if !json {
+                        let damage = match &report.damage {
+                            Some(damage) => damage.to_string(),
+                            None => "intact".to_string(),
+                        };
+                        status!(
+                            to_stderr,
+                            "{}: recovered {} bytes, {}",
+                            file.path.display(),
+                            report.recovered,
+                            damage
+                        );
+                    }
+                    match report.damage {
+                        Some(damage) => {
+                            salvaged += 1;
+                            exit_code = ExitCode::from(damage.error().exit_code());
+                        }
+                        None => intact += 1,
+                    }
+                }
+                Err(e) => {
+                    if !json {
+                        eprintln!("Error: {}: {}", file.path.display(), e);
+                    }
+                    failed += 1;
+                    exit_code = ExitCode::from(e.exit_code());
+                }
+            }
+        },
+    );
+
+    if files.len() > 1 && !json {
+        status!(
+            to_stderr,
+            "{} files: {} intact, {} salvaged, {} failed",
+            files.len(),
+            intact,
+            salvaged,
+            failed
+        );
+    }
+    exit_code
+}
+
+/// Decrypts what can be recovered from `input`, keeping the output either way.
+fn salvage_file(input: &InputFile, args: &DecryptArgs, secret: &Secret) -> Result<Salvaged> {
+    // finding the trailer needs a seekable file, so stdin is out
+    let file = input.path.as_path();
+    if !file.is_file() {
+        return Err(EasyCrabError::NotAFile(file.to_path_buf()));
+    }
+    let new_file_name = checked_output_path(input, args)?;
+
+    let mut salvager = Salvager::from_header_key(BufReader::new(File::open(file)?))?;
+    let password_line = secret.check(salvager.header())?;
+    let mut output = create_output(args, &new_file_name)?;
+    let (report, stages) = salvager.salvage_to(output.as_mut(), args.threads)?;
+    if let Some(Output::File(file)) = output {
+        file.commit()?;
+    }
+    Ok(Salvaged {
+        report,
+        password_line,
+        stages: args.verbose.then_some(stages),
+    })
+}
+
+fn output_path(file: &InputFile, args: &DecryptArgs, stored_name: Option<&str>) -> PathBuf {
+    if let Some(output) = &args.output {
+        return output.clone();
+    }
+    if file.path == Path::new(STDIO) {
+        return PathBuf::from(STDIO);
+    }
+    let path = match &args.output_dir {
+        Some(dir) => dir.join(&file.relative).with_extension(""),
+        None => file.path.with_extension(""),
+    };
+    match stored_name {
+        Some(name) => path.with_file_name(name),
+        None => path,
     }
+}
 
-    let key = GenericArray::from_slice(&hash[..32]);
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
 
-    let checksum_offset = enc_file.seek(SeekFrom::End(-0x20)).unwrap();
-    let mut src_checksum = [0u8; 32];
-    enc_file.read_exact(&mut src_checksum).unwrap();
-    let data_length = checksum_offset - DATA_OFFSET;
+/// Reads the file name stored in the header when `--use-stored-name` asks for it.
+fn stored_name(input: &InputFile, args: &DecryptArgs) -> Result<Option<String>> {
+    if !args.use_stored_name || input.path == Path::new(STDIO) {
+        return Ok(None);
+    }
+    let header = EzcHeader::read_from(&mut BufReader::new(File::open(&input.path)?))?;
+    Ok(header.stored_name())
+}
 
-    let aes_cbc_dec = CBCDecryptor::<Aes256Dec>::new(key, &iv.into());
-    let src_checksum = aes_cbc_dec
-        .decrypt_padded_mut::<Pkcs7>(&mut src_checksum)
-        .unwrap();
-    println!("Source checksum: {}", hexstring(src_checksum));
+/// Picks the output path for `input`, refusing to clobber anything unless allowed.
+fn checked_output_path(input: &InputFile, args: &DecryptArgs) -> Result<PathBuf> {
+    let file = input.path.as_path();
+    let new_file_name = output_path(input, args, stored_name(input, args)?.as_deref());
+    if !args.no_write && new_file_name != Path::new(STDIO) {
+        if is_same_file(file, &new_file_name) {
+            return Err(EasyCrabError::DestinationIsSource(new_file_name));
+        }
+        if new_file_name.exists() & !args.force {
+            return Err(EasyCrabError::DestinationExists(new_file_name));
+        }
+    }
+    Ok(new_file_name)
+}
 
-    enc_file.seek(SeekFrom::Start(DATA_OFFSET)).unwrap();
-    let mut aes_cbc_dec = CBCDecryptor::<Aes256Dec>::new(key, &iv.into());
-    let mut processed_bytes = 0;
-    let mut buf = [0u8; CHUNK_SIZE as usize];
-    let mut dec_file = if args.no_write {
+fn create_output(args: &DecryptArgs, path: &Path) -> Result<Option<Output>> {
+    Ok(if args.no_write {
         None
+    } else if path == Path::new(STDIO) {
+        Some(Output::Stdout(BufWriter::new(io::stdout().lock())))
+    } else {
+        Some(Output::File(AtomicFile::create(path)?))
+    })
+}
+
+fn decrypt_file(
+    input: &InputFile,
+    args: &DecryptArgs,
+    secret: &Secret,
+    verbose: bool,
+    to_stderr: bool,
+) -> Result<Decrypted> {
+    let file = input.path.as_path();
+    let from_stdin = file == Path::new(STDIO);
+    if !from_stdin && !file.is_file() {
+        return Err(EasyCrabError::NotAFile(file.to_path_buf()));
+    }
+
+    let new_file_name = checked_output_path(input, args)?;
+    let create_output = || create_output(args, &new_file_name);
+
+    let (mut decrypted, output) = if from_stdin {
+        let decryptor = StreamDecryptor::from_header_key(io::stdin().lock())?;
+        let password_line = secret.check(decryptor.header())?;
+        if verbose {
+            let header = decryptor.header();
+            status!(
+                to_stderr,
+                "Decrypting Easycrypt V{}.{} file...",
+                header.major,
+                header.minor
+            );
+        }
+        let mut output = create_output()?;
+        let summary = decryptor.decrypt_to(output.as_mut())?;
+        let decrypted = Decrypted {
+            summary,
+            password_line,
+            stages: None,
+            discarded: false,
+        };
+        (decrypted, output)
+    } else {
+        let mut decryptor = Decryptor::from_header_key(BufReader::new(File::open(file)?))?;
+        let password_line = secret.check(decryptor.header())?;
+        if verbose {
+            let header = decryptor.header();
+            status!(
+                to_stderr,
+                "Decrypting Easycrypt V{}.{} file...",
+                header.major,
+                header.minor
+            );
+        }
+        let mut output = create_output()?;
+        let (summary, stages) = decryptor.decrypt_pipelined(output.as_mut(), args.threads)?;
+        let decrypted = Decrypted {
+            summary,
+            password_line,
+            stages: args.verbose.then_some(stages),
+            discarded: false,
+        };
+        (decrypted, output)
+    };
+    // an uncommitted file is removed when dropped
+    let keep = decrypted.summary.is_match() || args.keep_on_mismatch();
+    if let Some(Output::File(file)) = output {
+        if keep {
+            file.commit()?;
+        } else {
+            decrypted.discarded = true;
+        }
+    }
+    let summary = &decrypted.summary;
+
+    if verbose {
+        status!(
+            to_stderr,
+            "Source checksum: {}",
+            hexstring(&summary.src_checksum)
+        );
+        status!(
+            to_stderr,
+            "Calculated checksum: {}",
+            hexstring(&summary.calc_checksum)
+        );
+        if !summary.is_match() {
+            status!(to_stderr, "Warning: checksum mismatch");
+        }
+    }
+    Ok(decrypted)
+}
+
+fn verify(mut args: VerifyArgs, json: bool) -> ExitCode {
+    let files = match expand_inputs(&args.files, args.recursive) {
+        Ok(files) => files,
+        Err(e) => {
+            report_error(&e, json);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    let secret = match args.secret.resolve() {
+        Ok(secret) => secret,
+        Err(e) => {
+            report_error(&e, json);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+
+    let mut exit_code = ExitCode::SUCCESS;
+    run_ordered(
+        &files,
+        args.jobs,
+        |file| verify_file(&file.path, &secret, &args),
+        |file, result| {
+            if json {
+                println!("{}", summary_json(&file.path, &result));
+            } else if let Ok(Decrypted {
+                stages: Some(stages),
+                ..
+            }) = &result
+            {
+                // keep stdout to one line per file
+                eprintln!("{}: {}", file.path.display(), stages);
+            }
+            match result.and_then(|decrypted| decrypted.summary.verify().map(|_| decrypted)) {
+                Ok(_) if json => {}
+                Ok(Decrypted {
+                    password_line: Some(line),
+                    ..
+                }) => println!("OK\t{}\t{}", file.path.display(), line),
+                Ok(_) => println!("OK\t{}", file.path.display()),
+                Err(e) => {
+                    if !json {
+                        println!("FAIL\t{}\t{}", file.path.display(), e.kind());
+                    }
+                    exit_code = ExitCode::from(e.exit_code());
+                }
+            }
+        },
+    );
+    exit_code
+}
+
+fn verify_file(file: &Path, secret: &Secret, args: &VerifyArgs) -> Result<Decrypted> {
+    if file == Path::new(STDIO) {
+        let decryptor = StreamDecryptor::from_header_key(io::stdin().lock())?;
+        let password_line = secret.check(decryptor.header())?;
+        return Ok(Decrypted {
+            summary: decryptor.decrypt_to(None::<io::Sink>)?,
+            password_line,
+            stages: None,
+            discarded: false,
+        });
+    }
+    if !file.is_file() {
+        return Err(EasyCrabError::NotAFile(file.to_path_buf()));
+    }
+    let mut decryptor = Decryptor::from_header_key(BufReader::new(File::open(file)?))?;
+    let password_line = secret.check(decryptor.header())?;
+    let (summary, stages) = decryptor.decrypt_pipelined(None::<io::Sink>, args.threads)?;
+    Ok(Decrypted {
+        summary,
+        password_line,
+        stages: args.verbose.then_some(stages),
+        discarded: false,
+    })
+}
+
+fn extract(mut args: ExtractArgs, json: bool) -> Result<()> {
+    // random access needs a seekable file, so stdin is out
+    if !args.file.is_file() {
+        return Err(EasyCrabError::NotAFile(args.file));
+    }
+    let to_stdout = args.output == Path::new(STDIO);
+    if !to_stdout {
+        if is_same_file(&args.file, &args.output) {
+            return Err(EasyCrabError::DestinationIsSource(args.output));
+        }
+        if args.output.exists() & !args.force {
+            return Err(EasyCrabError::DestinationExists(args.output));
+        }
+    }
+
+    let secret = args.secret.resolve()?;
+    let decryptor = Decryptor::from_header_key(BufReader::new(File::open(&args.file)?))?;
+    let password_line = secret.check(decryptor.header())?;
+    let mut reader = EzcReader::from_decryptor(decryptor)?;
+    let plain_length = reader.plain_length();
+    if args.offset > plain_length {
+        return Err(EasyCrabError::OffsetOutOfRange(args.offset, plain_length));
+    }
+    reader.seek(SeekFrom::Start(args.offset))?;
+    let mut range = reader.take(args.length.unwrap_or(u64::MAX));
+
+    let length = if to_stdout {
+        let mut out = BufWriter::new(io::stdout().lock());
+        let length = io::copy(&mut range, &mut out)?;
+        out.flush()?;
+        length
     } else {
-        Some(BufWriter::new(File::create(new_file_name).unwrap()))
+        let mut out = AtomicFile::create(&args.output)?;
+        let length = io::copy(&mut range, &mut out)?;
+        out.commit()?;
+        length
+    };
+
+    if json {
+        let mut record = json!({
+            "file": args.file.to_string_lossy(),
+            "status": "ok",
+            "output": args.output.to_string_lossy(),
+            "offset": args.offset,
+            "length": length,
+            "plain_length": plain_length,
+        });
+        if let Some(line) = password_line {
+            record["password_line"] = line.into();
+        }
+        status!(to_stdout, "{}", record);
+    } else {
+        status!(
+            to_stdout,
+            "Extracted {} of {} bytes at offset {}, checksum not verified{}",
+            length,
+            plain_length,
+            args.offset,
+            password_note(password_line)
+        );
+    }
+    Ok(())
+}
+
+fn encrypt(mut args: EncryptArgs, json: bool) -> Result<()> {
+    if !args.file.is_file() {
+        return Err(EasyCrabError::NotAFile(args.file));
+    }
+
+    let mut new_file_name = args.file.clone().into_os_string();
+    new_file_name.push(".ezc");
+    let new_file_name = PathBuf::from(new_file_name);
+    if new_file_name.exists() & !args.force {
+        return Err(EasyCrabError::DestinationExists(new_file_name));
+    }
+
+    let password = match args.password.take() {
+        Some(password) => take_password(password),
+        None => args.source.read(true)?,
     };
-    let mut filehasher = Sha1::new();
+    let src_file = BufReader::new(File::open(&args.file)?);
+    let encryptor = Encryptor::new(&password)?;
+    let header = encryptor.header();
+    if !json {
+        println!(
+            "Encrypting Easycrypt V{}.{} file...",
+            header.major, header.minor
+        );
+    }
+
+    let mut enc_file = AtomicFile::create(&new_file_name)?;
+    let checksum = encryptor.encrypt_to(src_file, &mut enc_file)?;
+    enc_file.commit()?;
+    if json {
+        println!(
+            "{}",
+            json!({
+                "file": args.file.to_string_lossy(),
+                "status": "ok",
+                "output": new_file_name.to_string_lossy(),
+                "version": format!("{}.{}", header.major, header.minor),
+                "src_checksum": hexstring(&checksum),
+            })
+        );
+    } else {
+        println!("Source checksum: {}", hexstring(&checksum));
+    }
+    Ok(())
+}
+
+fn info(args: InfoArgs, json: bool) -> ExitCode {
+    let mut exit_code = ExitCode::SUCCESS;
+    for (i, file) in args.files.iter().enumerate() {
+        if !json && i > 0 {
+            println!();
+        }
+        if let Err(e) = info_file(file, json) {
+            if json {
+                println!("{}", error_json(file, &e));
+            } else {
+                eprintln!("Error: {}", e);
+            }
+            exit_code = ExitCode::from(e.exit_code());
+        }
+    }
+    exit_code
+}
+
+fn info_file(file: &Path, json: bool) -> Result<()> {
+    if !file.is_file() {
+        return Err(EasyCrabError::NotAFile(file.to_path_buf()));
+    }
+
+    let mut enc_file = BufReader::new(File::open(file)?);
+    let info = FileInfo::read_from(&mut enc_file)?;
+    let header = &info.header;
+    let src_checksum = info.src_checksum.as_ref().map(|c| hexstring(c));
+
+    if json {
+        println!(
+            "{}",
+            json!({
+                "file": file.to_string_lossy(),
+                "status": "ok",
+                "version": format!("{}.{}", header.major, header.minor),
+                "file_length": info.file_length,
+                "data_length": info.data_length,
+                "aligned": info.is_aligned(),
+                "iv": hexstring(&header.iv),
+                "salt": hexstring(&header.salt),
+                "hash": hexstring(&header.hash),
+                "reserved": hexstring(&header.reserved),
+                "src_checksum": src_checksum,
+                "stored_name": header.stored_name(),
+            })
+        );
+        return Ok(());
+    }
+
+    println!("File: {}", file.display());
+    println!("Version: V{}.{}", header.major, header.minor);
+    println!("File size: {}", info.file_length);
+    println!(
+        "Payload length: {} ({})",
+        info.data_length,
+        if info.is_aligned() {
+            "block aligned"
+        } else {
+            "not block aligned"
+        }
+    );
+    println!("IV: {}", hexstring(&header.iv));
+    println!("Salt: {}", hexstring(&header.salt));
+    println!("Password hash: {}", hexstring(&header.hash));
+    println!(
+        "Source checksum: {}",
+        src_checksum.as_deref().unwrap_or("unavailable")
+    );
+    if let Some(name) = header.stored_name() {
+        println!("Stored name: {}", name);
+    }
+    println!("Reserved (not decoded):");
+    for (i, line) in header.reserved.chunks(16).enumerate() {
+        let ascii: String = line
+            .iter()
+            .map(|&c| if c.is_ascii_graphic() { c as char } else { '.' })
+            .collect();
+        println!(
+            "  {:04x}  {:<32}  {}",
+            RESERVED_OFFSET as usize + i * 16,
+            hexstring(line),
+            ascii
+        );
+    }
+    Ok(())
+}
+
+fn crack(args: CrackArgs, json: bool) -> Result<()> {
+    if !args.file.is_file() {
+        return Err(EasyCrabError::NotAFile(args.file));
+    }
 
-    for _ in 0..((data_length - 0x10) / CHUNK_SIZE) {
-        enc_file.read_exact(&mut buf).unwrap();
-        unsafe {
-            aes_cbc_dec.decrypt_blocks_mut(std::mem::transmute::<
-                _,
-                &mut [GenericArray<u8, U16>; (CHUNK_SIZE / 0x10) as usize],
-            >(&mut buf));
+    let mut enc_file = BufReader::new(File::open(&args.file)?);
+    let header = EzcHeader::read_from(&mut enc_file)?;
+
+    // a read error ends the wordlist early and is reported unless a password
+    // was found before it
+    let read_error = Mutex::new(None);
+    let words = match &args.wordlist {
+        Some(path) => {
+            let wordlist = BufReader::new(File::open(path)?);
+            let words = wordlist
+                .split(b'\n')
+                .map_while(|line| line.map_err(|e| *read_error.lock().unwrap() = Some(e)).ok())
+                .map(|mut line| {
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    line
+                });
+            Some(apply_rules(words, &args.rules))
         }
-        filehasher.update(buf);
-        if let Some(ref mut f) = dec_file {
-            f.write_all(&buf).unwrap();
+        None => None,
+    };
+    if let Some(mask) = args.mask.as_ref().filter(|_| !json) {
+        println!("Mask keyspace: {} candidates", mask.keyspace());
+    }
+    let masked = args.mask.iter().flat_map(|mask| mask.candidates());
+    let candidates = words.into_iter().flatten().chain(masked);
+
+    match find_password(&header, candidates, args.jobs) {
+        Some(password) => {
+            let password = String::from_utf8_lossy(&password);
+            if json {
+                println!(
+                    "{}",
+                    json!({ "file": args.file.to_string_lossy(), "status": "found", "password": password })
+                );
+            } else {
+                println!("Password found: {}", password);
+            }
+            Ok(())
         }
-        processed_bytes += CHUNK_SIZE;
+        None => match read_error.into_inner().unwrap() {
+            Some(e) => Err(e.into()),
+            None => Err(EasyCrabError::PasswordNotFound),
+        },
     }
+}
 
-    let _ = enc_file.read(&mut buf).unwrap();
-    let unpadded = aes_cbc_dec
-        .decrypt_padded_mut::<Pkcs7>(&mut buf[0..(data_length - processed_bytes) as usize])
-        .unwrap();
-    filehasher.update(unpadded);
-    if let Some(ref mut f) = dec_file {
-        f.write_all(unpadded).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        let argv = args.iter().map(OsString::from).collect();
+        Cli::try_parse_from(legacy_argv(argv)).unwrap()
+    }
+
+    #[test]
+    fn json_before_subcommand() {
+        let cli = parse(&["easycrab", "--json", "verify", "-p", "secret", "x.ezc"]);
+        assert!(cli.json);
+        let Command::Verify(args) = cli.command else {
+            panic!("not parsed as verify");
+        };
+        assert_eq!(args.secret.password.as_deref(), Some("secret"));
+        assert_eq!(args.files, [PathBuf::from("x.ezc")]);
+
+        let cli = parse(&["easycrab", "--json", "info", "x.ezc"]);
+        assert!(cli.json);
+        assert!(matches!(cli.command, Command::Info(_)));
+        assert!(matches!(
+            parse(&["easycrab", "inspect", "x.ezc"]).command,
+            Command::Info(_)
+        ));
     }
 
-    let calc_checksum = filehasher.finalize();
-    println!("Calculated checksum: {}", hexstring(&calc_checksum));
-    if GenericArray::from_slice(src_checksum) != &calc_checksum {
-        println!("Warning: checksum mismatch");
+    #[test]
+    fn legacy_form() {
+        let cli = parse(&["easycrab", "--json", "-f", "x.ezc", "secret", "--no-write"]);
+        assert!(cli.json);
+        let Command::Decrypt(args) = cli.command else {
+            panic!("not parsed as decrypt");
+        };
+        assert!(args.force && args.no_write);
+        assert_eq!(args.secret.password.as_deref(), Some("secret"));
+        assert_eq!(args.files, [PathBuf::from("x.ezc")]);
+
+        // a file named like a subcommand, and a password starting with a dash
+        let Command::Decrypt(args) = parse(&["easycrab", "--", "info", "-pw"]).command else {
+            panic!("not parsed as decrypt");
+        };
+        assert_eq!(args.secret.password.as_deref(), Some("-pw"));
+        assert_eq!(args.files, [PathBuf::from("info")]);
+
+        let Command::Decrypt(args) = parse(&["easycrab", "--override-password", "x.ezc"]).command
+        else {
+            panic!("not parsed as decrypt");
+        };
+        assert!(args.secret.no_password);
+        assert_eq!(args.secret.password, None);
     }
 }
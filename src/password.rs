@@ -0,0 +1,170 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+use zeroize::Zeroizing;
+
+use crate::{EasyCrabError, Result, SecretBuf};
+
+/// Password bytes, locked in memory and wiped when dropped
+pub type Password = SecretBuf;
+
+/// Longest password [`read_password_file`] and [`read_password_fd`] accept
+pub const MAX_PASSWORD_LEN: usize = 1024;
+
+/// Prompts on the terminal without echoing the input.
+pub fn prompt_password(prompt: &str) -> Result<Password> {
+    let password = rpassword::prompt_password(prompt).map_err(|e| {
+        EasyCrabError::PasswordUnavailable(format!("cannot prompt on the terminal ({})", e))
+    })?;
+    Ok(SecretBuf::from_slice(Zeroizing::new(password).as_bytes()))
+}
+
+/// Reads the first line of `path`, at most [`MAX_PASSWORD_LEN`] bytes.
+pub fn read_password_file(path: &Path) -> Result<Password> {
+    let file = File::open(path)
+        .map_err(|e| EasyCrabError::PasswordUnavailable(format!("{}: {}", path.display(), e)))?;
+    read_password_from(file)
+}
+
+/// Reads the first line of the already open file descriptor `fd`, at most
+/// [`MAX_PASSWORD_LEN`] bytes.
+#[cfg(unix)]
+pub fn read_password_fd(fd: i32) -> Result<Password> {
+    let path = format!("/dev/fd/{}", fd);
+    let file = File::open(&path)
+        .map_err(|e| EasyCrabError::PasswordUnavailable(format!("{}: {}", path, e)))?;
+    read_password_from(file)
+}
+
+/// Reads one candidate password per line of `path`.
+///
+/// Entry `i` comes from line `i + 1`; empty lines are kept so the numbering
+/// matches the file.
+pub fn read_password_list(path: &Path) -> Result<Vec<Password>> {
+    let mut file = File::open(path)
+        .map_err(|e| EasyCrabError::PasswordUnavailable(format!("{}: {}", path.display(), e)))?;
+    // sized up front so the buffer is not reallocated, leaving copies behind
+    let length = file.metadata().map_or(0, |m| m.len() as usize);
+    let mut contents = Zeroizing::new(Vec::with_capacity(length));
+    file.read_to_end(&mut contents)
+        .map_err(|e| EasyCrabError::PasswordUnavailable(e.to_string()))?;
+    Ok(split_password_list(&contents))
+}
+
+fn split_password_list(contents: &[u8]) -> Vec<Password> {
+    let contents = contents.strip_suffix(b"\n").unwrap_or(contents);
+    contents
+        .split(|&c| c == b'\n')
+        .map(|line| SecretBuf::from_slice(line.strip_suffix(b"\r").unwrap_or(line)))
+        .collect()
+}
+
+/// Reads the password from the environment variable `name`.
+pub fn read_password_env(name: &str) -> Result<Password> {
+    match env::var(name) {
+        Ok(value) => Ok(SecretBuf::from_slice(Zeroizing::new(value).as_bytes())),
+        Err(e) => Err(EasyCrabError::PasswordUnavailable(format!(
+            "{}: {}",
+            name, e
+        ))),
+    }
+}
+
+/// Reads up to the first line ending.
+fn read_password_from<R: Read>(mut reader: R) -> Result<Password> {
+    // byte by byte and unbuffered, so a terminal or a pipe that stays open
+    // does not block after the first line, nothing past it is consumed and
+    // no copy of the secret is left behind in a reader buffer; the line
+    // buffer is fixed, as a growing one would leave copies when reallocated
+    let too_long = || {
+        EasyCrabError::PasswordUnavailable(format!(
+            "password is longer than {} bytes",
+            MAX_PASSWORD_LEN
+        ))
+    };
+    // room for a trailing '\r'
+    let mut line = Zeroizing::new([0u8; MAX_PASSWORD_LEN + 1]);
+    let mut length = 0;
+    let mut byte = Zeroizing::new([0u8; 1]);
+    loop {
+        match reader.read(&mut byte[..]) {
+            Ok(0) => break,
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) if length == line.len() => return Err(too_long()),
+            Ok(_) => {
+                line[length] = byte[0];
+                length += 1;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(EasyCrabError::PasswordUnavailable(e.to_string())),
+        }
+    }
+    let line = &line[..length];
+    let password = line.strip_suffix(b"\r").unwrap_or(line);
+    if password.len() > MAX_PASSWORD_LEN {
+        return Err(too_long());
+    }
+    Ok(SecretBuf::from_slice(password))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &[u8]) -> Result<Vec<u8>> {
+        read_password_from(input).map(|p| p.to_vec())
+    }
+
+    #[test]
+    fn first_line_only() {
+        assert_eq!(read(b"secret\nsecond\n").unwrap(), b"secret");
+        assert_eq!(read(b"secret").unwrap(), b"secret");
+        assert_eq!(read(b"\nsecret").unwrap(), b"");
+        assert_eq!(read(b"").unwrap(), b"");
+
+        // nothing past the line ending is consumed
+        let mut input = &b"secret\nsecond\n"[..];
+        read_password_from(&mut input).unwrap();
+        assert_eq!(input, b"second\n");
+    }
+
+    #[test]
+    fn strips_carriage_return() {
+        assert_eq!(read(b"secret\r\n").unwrap(), b"secret");
+        assert_eq!(read(b"secret\r").unwrap(), b"secret");
+        // only the one before the line ending
+        assert_eq!(read(b"sec\rret\r\r\n").unwrap(), b"sec\rret\r");
+    }
+
+    #[test]
+    fn length_limit() {
+        let max = vec![b'x'; MAX_PASSWORD_LEN];
+        assert_eq!(read(&max).unwrap(), max);
+        assert_eq!(read(&[&max[..], b"\r\n"].concat()).unwrap(), max);
+
+        let long = vec![b'x'; MAX_PASSWORD_LEN + 1];
+        assert!(matches!(
+            read(&long),
+            Err(EasyCrabError::PasswordUnavailable(_))
+        ));
+        assert!(read(&[&long[..], b"\r\n"].concat()).is_err());
+        assert!(read(&vec![b'x'; 2 * MAX_PASSWORD_LEN]).is_err());
+    }
+
+    #[test]
+    fn list_keeps_numbering() {
+        let list: Vec<_> = split_password_list(b"one\r\n\ntwo\n\r\nthree\n")
+            .iter()
+            .map(|p| p.to_vec())
+            .collect();
+        assert_eq!(
+            list,
+            [&b"one"[..], b"", b"two", b"", b"three"].map(<[u8]>::to_vec)
+        );
+        // without a final line ending, and a trailing empty line
+        assert_eq!(split_password_list(b"one\ntwo").len(), 2);
+        assert_eq!(split_password_list(b"one\n\n").len(), 2);
+    }
+}
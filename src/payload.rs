@@ -0,0 +1,110 @@
+use generic_array::GenericArray;
+
+use aes::{
+    cipher::{block_padding::Pkcs7, inout::InOutBuf, BlockDecryptMut, KeyIvInit},
+    Aes256Dec,
+};
+use cbc::Decryptor as CBCDecryptor;
+
+use crate::{EasyCrabError, Result};
+
+/// AES-256-CBC decryption of an EasyCrypt payload, one chunk at a time
+///
+/// Chunks must be a whole number of 16-byte blocks and are decrypted in place
+/// with a single call into the cipher. The CBC state carries over from one
+/// chunk to the next, so the payload can be fed in pieces of any aligned size;
+/// the final piece goes through [`finish`](Self::finish) to strip the padding.
+pub struct PayloadDecryptor {
+    cipher: CBCDecryptor<Aes256Dec>,
+}
+
+impl PayloadDecryptor {
+    /// Starts decrypting with a 32-byte `key`.
+    ///
+    /// `iv` is the header IV at the start of the payload, or the ciphertext
+    /// block just before the first chunk when starting anywhere else.
+    pub fn new(key: &[u8], iv: &[u8; 16]) -> Self {
+        PayloadDecryptor {
+            cipher: CBCDecryptor::<Aes256Dec>::new(GenericArray::from_slice(key), iv.into()),
+        }
+    }
+
+    /// Decrypts `chunk` in place.
+    ///
+    /// # Panics
+    ///
+    /// If the length of `chunk` is not a multiple of 16.
+    pub fn decrypt_chunk(&mut self, chunk: &mut [u8]) {
+        let (blocks, tail) = InOutBuf::from(chunk).into_chunks();
+        assert!(tail.is_empty(), "chunk is not a whole number of blocks");
+        self.cipher.decrypt_blocks_inout_mut(blocks);
+    }
+
+    /// Decrypts the last piece of the payload in place and strips its PKCS#7 padding.
+    pub fn finish(self, last: &mut [u8]) -> Result<&[u8]> {
+        self.cipher
+            .decrypt_padded_mut::<Pkcs7>(last)
+            .map_err(|_| EasyCrabError::BadPadding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::{cipher::BlockEncryptMut, Aes256Enc};
+
+    const KEY: [u8; 32] = [3; 32];
+    const IV: [u8; 16] = [5; 16];
+
+    /// PKCS#7-padded ciphertext of 1000 bytes
+    fn ciphertext() -> (Vec<u8>, Vec<u8>) {
+        let plain: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let mut buf = plain.clone();
+        buf.resize(1008, 0);
+        cbc::Encryptor::<Aes256Enc>::new(&KEY.into(), &IV.into())
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, plain.len())
+            .unwrap();
+        (plain, buf)
+    }
+
+    #[test]
+    fn chunked_matches_one_shot() {
+        let (plain, ciphertext) = ciphertext();
+        let mut one_shot = ciphertext.clone();
+        let expected = CBCDecryptor::<Aes256Dec>::new(&KEY.into(), &IV.into())
+            .decrypt_padded_mut::<Pkcs7>(&mut one_shot)
+            .unwrap();
+        assert_eq!(expected, plain);
+
+        for split in [16, 32, 48, 496, 992] {
+            let mut buf = ciphertext.clone();
+            let (bulk, last) = buf.split_at_mut(ciphertext.len() - 16);
+            let mut payload = PayloadDecryptor::new(&KEY, &IV);
+            for chunk in bulk.chunks_mut(split) {
+                payload.decrypt_chunk(chunk);
+            }
+            let tail = payload.finish(last).unwrap().to_vec();
+            assert_eq!([&bulk[..], &tail].concat(), plain, "split {}", split);
+        }
+    }
+
+    #[test]
+    fn finish_rejects_bad_padding() {
+        let (_, mut ciphertext) = ciphertext();
+        // garbles the last plaintext block, padding included
+        ciphertext[1000] ^= 1;
+        let (bulk, last) = ciphertext.split_at_mut(992);
+        let mut payload = PayloadDecryptor::new(&KEY, &IV);
+        payload.decrypt_chunk(bulk);
+        assert!(matches!(
+            payload.finish(last),
+            Err(EasyCrabError::BadPadding)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "whole number of blocks")]
+    fn misaligned_chunk_panics() {
+        PayloadDecryptor::new(&KEY, &IV).decrypt_chunk(&mut [0u8; 24]);
+    }
+}
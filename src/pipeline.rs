@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::prelude::*;
+use std::num::NonZeroUsize;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use digest::Digest;
+use sha1::Sha1;
+
+use crate::{PayloadDecryptor, Result, SecretBuf, CHUNK_SIZE};
+
+/// Bytes read, decrypted, hashed and written as one unit
+const BATCH_SIZE: usize = 8 * CHUNK_SIZE as usize;
+
+/// Work done by one pipeline stage
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StageStats {
+    pub bytes: u64,
+    /// Time spent working, not waiting on other stages; summed over threads
+    pub busy: Duration,
+}
+
+impl StageStats {
+    /// Bytes per second while busy, if the stage did any work
+    pub fn throughput(&self) -> Option<f64> {
+        let secs = self.busy.as_secs_f64();
+        (self.bytes > 0 && secs > 0.0).then(|| self.bytes as f64 / secs)
+    }
+
+    fn time<T>(&mut self, bytes: usize, work: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = work();
+        self.busy += start.elapsed();
+        self.bytes += bytes as u64;
+        result
+    }
+}
+
+/// Per-stage figures from [`crate::Decryptor::decrypt_pipelined`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PipelineStats {
+    pub read: StageStats,
+    /// Summed over all decryption threads
+    pub decrypt: StageStats,
+    pub hash: StageStats,
+    pub write: StageStats,
+    pub threads: usize,
+    /// Wall-clock time of the whole run
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for PipelineStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn rate(stats: &StageStats) -> String {
+            match stats.throughput() {
+                Some(rate) => format!("{:.1} MiB/s", rate / (1024.0 * 1024.0)),
+                None => "-".to_string(),
+            }
+        }
+        let overall = StageStats {
+            bytes: self.read.bytes,
+            busy: self.elapsed,
+        };
+        write!(
+            f,
+            "read {}, decrypt {}",
+            rate(&self.read),
+            rate(&self.decrypt)
+        )?;
+        if self.threads > 1 {
+            write!(f, " on each of {} threads", self.threads)?;
+        }
+        write!(
+            f,
+            ", hash {}, write {}, overall {}",
+            rate(&self.hash),
+            rate(&self.write),
+            rate(&overall)
+        )
+    }
+}
+
+/// A batch of payload on its way through the pipeline
+struct Batch {
+    seq: u64,
+    buf: SecretBuf,
+    len: usize,
+    /// Ciphertext block preceding the batch
+    iv: [u8; 16],
+}
+
+/// What is left once the bulk of the payload went through the pipeline
+pub(crate) struct Bulk {
+    /// Last ciphertext block read, the IV for whatever follows
+    pub iv: [u8; 16],
+    pub filehasher: Sha1,
+    pub stats: PipelineStats,
+}
+
+/// Decrypts `length` bytes of whole blocks from `reader` into `out`.
+///
+/// A reader thread, `threads` decryption threads and a hashing thread are
+/// connected by bounded channels and share a fixed pool of buffers, so reading
+/// the next batch overlaps with decrypting, hashing and writing earlier ones.
+/// Writing stays on the calling thread, which lets `out` be a stdout lock.
+pub(crate) fn decrypt_bulk<R: Read + Send, W: Write>(
+    reader: &mut R,
+    key: &[u8],
+    iv: [u8; 16],
+    length: u64,
+    mut out: Option<W>,
+    threads: NonZeroUsize,
+) -> Result<Bulk> {
+    let start = Instant::now();
+    let threads = threads.get();
+    let pool_size = 2 * threads + 2;
+
+    let (free_tx, free_rx) = mpsc::sync_channel::<SecretBuf>(pool_size);
+    for _ in 0..pool_size {
+        free_tx.send(SecretBuf::new(BATCH_SIZE)).unwrap();
+    }
+    let (read_tx, read_rx) = mpsc::sync_channel::<Batch>(threads);
+    let (decrypt_tx, decrypt_rx) = mpsc::sync_channel::<Batch>(threads);
+    let (hash_tx, hash_rx) = mpsc::sync_channel::<Batch>(2);
+    // shared so the receiver is dropped, unblocking the reader, once every
+    // decryption thread has given up
+    let read_rx = Arc::new(Mutex::new(read_rx));
+
+    thread::scope(|s| {
+        let reading = s.spawn(move || read_stage(reader, iv, length, free_rx, read_tx));
+        let decrypting: Vec<_> = (0..threads)
+            .map(|_| {
+                let (read_rx, decrypt_tx) = (read_rx.clone(), decrypt_tx.clone());
+                s.spawn(move || decrypt_stage(key, read_rx, decrypt_tx))
+            })
+            .collect();
+        drop((read_rx, decrypt_tx));
+        let hashing = s.spawn(move || hash_stage(decrypt_rx, hash_tx));
+
+        let mut write = StageStats::default();
+        let written = (|| -> Result<()> {
+            for batch in hash_rx {
+                if let Some(ref mut f) = out {
+                    write.time(batch.len, || f.write_all(&batch.buf[..batch.len]))?;
+                }
+                // the reader may already be gone
+                let _ = free_tx.send(batch.buf);
+            }
+            Ok(())
+        })();
+        // unblock the other stages before joining them
+        drop(free_tx);
+
+        let (filehasher, hash) = hashing.join().unwrap();
+        let mut decrypt = StageStats::default();
+        for stats in decrypting.into_iter().map(|t| t.join().unwrap()) {
+            decrypt.bytes += stats.bytes;
+            decrypt.busy += stats.busy;
+        }
+        let (iv, read) = reading.join().unwrap()?;
+        written?;
+        Ok(Bulk {
+            iv,
+            filehasher,
+            stats: PipelineStats {
+                read,
+                decrypt,
+                hash,
+                write,
+                threads,
+                elapsed: start.elapsed(),
+            },
+        })
+    })
+}
+
+fn read_stage<R: Read>(
+    reader: &mut R,
+    mut iv: [u8; 16],
+    length: u64,
+    free_rx: Receiver<SecretBuf>,
+    read_tx: SyncSender<Batch>,
+) -> Result<([u8; 16], StageStats)> {
+    let mut stats = StageStats::default();
+    let mut remaining = length;
+    let mut seq = 0;
+    while remaining > 0 {
+        let Ok(mut buf) = free_rx.recv() else { break };
+        let len = buf.len().min(remaining as usize);
+        stats.time(len, || reader.read_exact(&mut buf[..len]))?;
+        let next_iv = buf[len - 0x10..len].try_into().unwrap();
+        if read_tx.send(Batch { seq, buf, len, iv }).is_err() {
+            break;
+        }
+        iv = next_iv;
+        remaining -= len as u64;
+        seq += 1;
+    }
+    Ok((iv, stats))
+}
+
+fn decrypt_stage(
+    key: &[u8],
+    read_rx: Arc<Mutex<Receiver<Batch>>>,
+    decrypt_tx: SyncSender<Batch>,
+) -> StageStats {
+    let mut stats = StageStats::default();
+    loop {
+        let Ok(mut batch) = read_rx.lock().unwrap().recv() else {
+            break;
+        };
+        stats.time(batch.len, || {
+            PayloadDecryptor::new(key, &batch.iv).decrypt_chunk(&mut batch.buf[..batch.len])
+        });
+        if decrypt_tx.send(batch).is_err() {
+            break;
+        }
+    }
+    stats
+}
+
+/// Hashes batches in order, whichever decryption thread finished them first.
+fn hash_stage(decrypt_rx: Receiver<Batch>, hash_tx: SyncSender<Batch>) -> (Sha1, StageStats) {
+    let mut stats = StageStats::default();
+    let mut filehasher = Sha1::new();
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    for batch in decrypt_rx {
+        pending.insert(batch.seq, batch);
+        while let Some(batch) = pending.remove(&next) {
+            stats.time(batch.len, || filehasher.update(&batch.buf[..batch.len]));
+            if hash_tx.send(batch).is_err() {
+                return (filehasher, stats);
+            }
+            next += 1;
+        }
+    }
+    (filehasher, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encrypt, plaintext, PASSWORD};
+    use crate::{EzcHeader, DATA_OFFSET};
+    use std::io::Cursor;
+
+    #[test]
+    fn batches_chain_their_ivs() {
+        for len in [
+            BATCH_SIZE - 16,
+            BATCH_SIZE,
+            BATCH_SIZE + 16,
+            3 * BATCH_SIZE + 0x1230,
+        ] {
+            let plain = plaintext(len);
+            let file = encrypt(&plain);
+            let header = EzcHeader::read_from(&mut &file[..]).unwrap();
+            assert!(header.check_password(PASSWORD));
+            // whole blocks only, so the padded last block is left over
+            let length = len as u64 / 16 * 16;
+            for threads in [1, 2, 5] {
+                let mut reader = Cursor::new(&file[DATA_OFFSET as usize..]);
+                let mut out = Vec::new();
+                let bulk = decrypt_bulk(
+                    &mut reader,
+                    &header.key(),
+                    header.iv,
+                    length,
+                    Some(&mut out),
+                    NonZeroUsize::new(threads).unwrap(),
+                )
+                .unwrap();
+                let what = format!("length {} on {} threads", len, threads);
+                assert!(out[..] == plain[..length as usize], "{}", what);
+                assert_eq!(reader.position(), length, "{}", what);
+                let last = &file[DATA_OFFSET as usize + length as usize - 16..][..16];
+                assert_eq!(bulk.iv, last, "{}", what);
+                assert_eq!(bulk.stats.read.bytes, length, "{}", what);
+                assert_eq!(bulk.stats.decrypt.bytes, length, "{}", what);
+                let mut hasher = Sha1::new();
+                hasher.update(&out);
+                assert_eq!(bulk.filehasher.finalize(), hasher.finalize(), "{}", what);
+            }
+        }
+    }
+}
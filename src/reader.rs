@@ -0,0 +1,221 @@
+use std::io::{self, prelude::*, SeekFrom};
+
+use crate::{Decryptor, EzcHeader, PayloadDecryptor, Result, SecretBuf, CHUNK_SIZE, DATA_OFFSET};
+
+/// Random-access plaintext view of an EasyCrypt file
+///
+/// Any CBC block can be decrypted from the ciphertext block before it, so
+/// reads only decrypt the chunks they touch and seeking is free. This makes it
+/// possible to open an archive or database stored inside an `.ezc` file
+/// without decrypting all of it. The SHA-1 trailer is not checked, since that
+/// needs the whole plaintext; use [`Decryptor`] for that.
+pub struct EzcReader<R> {
+    reader: R,
+    header: EzcHeader,
+    key: SecretBuf,
+    src_checksum: [u8; 20],
+    data_length: u64,
+    plain_length: u64,
+    pos: u64,
+    /// Plaintext of the chunk starting at `chunk_start`
+    chunk: SecretBuf,
+    chunk_start: u64,
+    chunk_len: usize,
+}
+
+impl<R: Read + Seek> EzcReader<R> {
+    /// Parses the header and checks `password` against the stored verifier.
+    pub fn new(reader: R, password: &[u8]) -> Result<Self> {
+        Self::from_decryptor(Decryptor::new(reader, password)?)
+    }
+
+    /// Parses the header and decrypts with the key stored in it.
+    pub fn from_header_key(reader: R) -> Result<Self> {
+        Self::from_decryptor(Decryptor::from_header_key(reader)?)
+    }
+
+    /// Takes over an opened file, decrypting its last block to learn the
+    /// plaintext length.
+    pub fn from_decryptor(decryptor: Decryptor<R>) -> Result<Self> {
+        let Decryptor {
+            reader,
+            header,
+            key,
+            src_checksum,
+            data_length,
+        } = decryptor;
+        let mut ezc = EzcReader {
+            reader,
+            header,
+            key,
+            src_checksum,
+            data_length,
+            plain_length: 0,
+            pos: 0,
+            chunk: SecretBuf::new(CHUNK_SIZE as usize),
+            chunk_start: 0,
+            chunk_len: 0,
+        };
+
+        let last_start = data_length - 0x10;
+        let iv = ezc.iv_at(last_start)?;
+        let mut last = SecretBuf::new(0x10);
+        ezc.reader.read_exact(&mut last)?;
+        let unpadded = PayloadDecryptor::new(&ezc.key, &iv).finish(&mut last)?;
+        ezc.plain_length = last_start + unpadded.len() as u64;
+        Ok(ezc)
+    }
+
+    pub fn header(&self) -> &EzcHeader {
+        &self.header
+    }
+
+    /// SHA-1 of the whole plaintext as stored in the encrypted trailer
+    pub fn src_checksum(&self) -> &[u8; 20] {
+        &self.src_checksum
+    }
+
+    /// Length of the plaintext
+    pub fn plain_length(&self) -> u64 {
+        self.plain_length
+    }
+
+    /// Returns the ciphertext block before payload offset `offset`, leaving
+    /// the reader at `offset`.
+    fn iv_at(&mut self, offset: u64) -> Result<[u8; 16]> {
+        if offset == 0 {
+            self.reader.seek(SeekFrom::Start(DATA_OFFSET))?;
+            return Ok(self.header.iv);
+        }
+        self.reader
+            .seek(SeekFrom::Start(DATA_OFFSET + offset - 0x10))?;
+        let mut iv = [0u8; 16];
+        self.reader.read_exact(&mut iv)?;
+        Ok(iv)
+    }
+
+    /// Decrypts the chunk holding `pos` into the cache.
+    fn load_chunk(&mut self) -> Result<()> {
+        let start = self.pos - self.pos % CHUNK_SIZE;
+        if self.chunk_len > 0 && start == self.chunk_start {
+            return Ok(());
+        }
+        let len = CHUNK_SIZE.min(self.data_length - start) as usize;
+        let iv = self.iv_at(start)?;
+        // invalidate first, in case reading fails halfway
+        self.chunk_len = 0;
+        self.reader.read_exact(&mut self.chunk[..len])?;
+        PayloadDecryptor::new(&self.key, &iv).decrypt_chunk(&mut self.chunk[..len]);
+        self.chunk_start = start;
+        self.chunk_len = len;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for EzcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.plain_length || buf.is_empty() {
+            return Ok(0);
+        }
+        self.load_chunk()?;
+        let offset = (self.pos - self.chunk_start) as usize;
+        // the last chunk still holds the padding
+        let available = (self.plain_length - self.pos).min((self.chunk_len - offset) as u64);
+        let n = buf.len().min(available as usize);
+        buf[..n].copy_from_slice(&self.chunk[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for EzcReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.plain_length, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encrypt, plaintext, PASSWORD, SIZES};
+    use std::io::Cursor;
+
+    fn read_at(reader: &mut EzcReader<Cursor<Vec<u8>>>, pos: u64, len: usize) -> Vec<u8> {
+        reader.seek(SeekFrom::Start(pos)).unwrap();
+        let mut buf = Vec::new();
+        reader.take(len as u64).read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_match_plaintext() {
+        let chunk = CHUNK_SIZE as usize;
+        for len in SIZES {
+            let plain = plaintext(len);
+            let mut reader = EzcReader::new(Cursor::new(encrypt(&plain)), PASSWORD).unwrap();
+            assert_eq!(reader.plain_length(), len as u64);
+
+            let mut all = Vec::new();
+            reader.read_to_end(&mut all).unwrap();
+            assert!(all == plain, "length {}", len);
+
+            // across chunk boundaries, into the padded last block and past the end
+            for (pos, n) in [
+                (0, 1),
+                (chunk - 7, 20),
+                (chunk, chunk),
+                (len.saturating_sub(20), 40),
+                (len.saturating_sub(1), 1),
+                (len, 10),
+                (len + 100, 10),
+            ] {
+                let expected = plain.get(pos.min(len)..(pos + n).min(len)).unwrap();
+                assert_eq!(
+                    read_at(&mut reader, pos as u64, n),
+                    expected,
+                    "length {}, {} bytes at {}",
+                    len,
+                    n,
+                    pos
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn seek_from_end_and_current() {
+        let plain = plaintext(3 * CHUNK_SIZE as usize + 5);
+        let mut reader = EzcReader::new(Cursor::new(encrypt(&plain)), PASSWORD).unwrap();
+        assert_eq!(
+            reader.seek(SeekFrom::End(-10)).unwrap(),
+            plain.len() as u64 - 10
+        );
+        assert_eq!(
+            reader.seek(SeekFrom::Current(-6)).unwrap(),
+            plain.len() as u64 - 16
+        );
+        let mut buf = [0u8; 16];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, plain[plain.len() - 16..]);
+        assert!(reader
+            .seek(SeekFrom::Current(-(plain.len() as i64) - 1))
+            .is_err());
+    }
+}
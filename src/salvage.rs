@@ -0,0 +1,264 @@
+use std::fmt;
+use std::io::{prelude::*, SeekFrom};
+use std::num::NonZeroUsize;
+
+use digest::Digest;
+
+use crate::pipeline::{self, PipelineStats};
+use crate::{EasyCrabError, EzcHeader, PayloadDecryptor, Result, SecretBuf, DATA_OFFSET};
+
+/// First sign of damage found while salvaging, with its file offset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Damage {
+    /// The checksum trailer is missing or does not decrypt, so the file was
+    /// most likely cut short. Readable data ends at the offset; the last
+    /// blocks recovered may be the remains of the trailer.
+    Trailer(u64),
+    /// The last payload block, at the offset, has invalid padding.
+    Padding(u64),
+    /// Everything decrypts, but the plaintext does not match the stored
+    /// checksum. CBC gives no hint where the damage is.
+    Checksum,
+}
+
+impl Damage {
+    /// File offset where the damage begins, if it is known
+    pub fn offset(&self) -> Option<u64> {
+        match *self {
+            Damage::Trailer(offset) | Damage::Padding(offset) => Some(offset),
+            Damage::Checksum => None,
+        }
+    }
+
+    /// The error a normal decryption of the file fails with
+    pub fn error(&self) -> EasyCrabError {
+        match self {
+            Damage::Trailer(_) => EasyCrabError::Truncated,
+            Damage::Padding(_) => EasyCrabError::BadPadding,
+            Damage::Checksum => EasyCrabError::ChecksumMismatch,
+        }
+    }
+}
+
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Damage::Trailer(offset) => write!(f, "trailer missing or damaged at offset {}", offset),
+            Damage::Padding(offset) => write!(f, "invalid padding at offset {}", offset),
+            Damage::Checksum => write!(f, "checksum mismatch, location unknown"),
+        }
+    }
+}
+
+/// What [`Salvager::salvage_to`] recovered
+#[derive(Clone, Debug)]
+pub struct SalvageReport {
+    pub major: u8,
+    pub minor: u8,
+    /// Bytes of plaintext written
+    pub recovered: u64,
+    /// SHA-1 of the plaintext as stored in the encrypted trailer, if readable
+    pub src_checksum: Option<[u8; 20]>,
+    /// SHA-1 of the plaintext actually recovered
+    pub calc_checksum: [u8; 20],
+    /// `None` if the file turned out to be intact
+    pub damage: Option<Damage>,
+}
+
+/// Decrypter for truncated or damaged EasyCrypt files
+///
+/// Only the header has to be intact. If the trailer is readable the payload
+/// ends where it begins, otherwise every complete block in the file is
+/// decrypted and nothing is unpadded, since the padding is likely gone.
+pub struct Salvager<R> {
+    reader: R,
+    header: EzcHeader,
+}
+
+impl<R: Read + Seek> Salvager<R> {
+    /// Parses the header and checks `password` against the stored verifier.
+    pub fn new(reader: R, password: &[u8]) -> Result<Self> {
+        let salvager = Self::from_header_key(reader)?;
+        if !salvager.header.check_password(password) {
+            return Err(EasyCrabError::WrongPassword);
+        }
+        Ok(salvager)
+    }
+
+    /// Parses the header and decrypts with the key stored in it.
+    pub fn from_header_key(mut reader: R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let header = EzcHeader::read_from(&mut reader)?;
+        Ok(Salvager { reader, header })
+    }
+
+    pub fn header(&self) -> &EzcHeader {
+        &self.header
+    }
+
+    /// Reads the trailer if the file is long enough and block aligned for one.
+    fn read_trailer(&mut self, file_length: u64) -> Result<Option<[u8; 20]>> {
+        let available = file_length - DATA_OFFSET;
+        if available < 0x10 + 0x20 || !available.is_multiple_of(0x10) {
+            return Ok(None);
+        }
+        self.reader.seek(SeekFrom::Start(file_length - 0x20))?;
+        let mut trailer = [0u8; 0x20];
+        self.reader.read_exact(&mut trailer)?;
+        Ok(self.header.decrypt_trailer(&trailer).ok())
+    }
+
+    /// Decrypts whatever can be recovered into `out` on up to `threads`
+    /// threads, like [`crate::Decryptor::decrypt_pipelined`].
+    pub fn salvage_to<W: Write>(
+        &mut self,
+        mut out: Option<W>,
+        threads: NonZeroUsize,
+    ) -> Result<(SalvageReport, PipelineStats)>
+    where
+        R: Send,
+    {
+        let file_length = self.reader.seek(SeekFrom::End(0))?;
+        let src_checksum = self.read_trailer(file_length)?;
+        let data_length = match src_checksum {
+            Some(_) => file_length - 0x20 - DATA_OFFSET,
+            None => (file_length - DATA_OFFSET) / 0x10 * 0x10,
+        };
+        // the padded last block is only special when the trailer vouches for it
+        let bulk_length = match src_checksum {
+            Some(_) => data_length - 0x10,
+            None => data_length,
+        };
+
+        let key = self.header.key();
+        self.reader.seek(SeekFrom::Start(DATA_OFFSET))?;
+        let bulk = pipeline::decrypt_bulk(
+            &mut self.reader,
+            &key,
+            self.header.iv,
+            bulk_length,
+            out.as_mut(),
+            threads,
+        )?;
+        let mut filehasher = bulk.filehasher;
+        let mut recovered = bulk_length;
+
+        let mut damage = match src_checksum {
+            Some(_) => {
+                let mut last = SecretBuf::new(0x10);
+                self.reader.read_exact(&mut last)?;
+                let mut raw = SecretBuf::from_slice(&last);
+                PayloadDecryptor::new(&key, &bulk.iv).decrypt_chunk(&mut raw);
+                let (plain, damage) = match PayloadDecryptor::new(&key, &bulk.iv).finish(&mut last)
+                {
+                    Ok(unpadded) => (unpadded, None),
+                    Err(_) => (
+                        &raw[..],
+                        Some(Damage::Padding(DATA_OFFSET + data_length - 0x10)),
+                    ),
+                };
+                filehasher.update(plain);
+                if let Some(ref mut f) = out {
+                    f.write_all(plain)?;
+                }
+                recovered += plain.len() as u64;
+                damage
+            }
+            None => Some(Damage::Trailer(DATA_OFFSET + data_length)),
+        };
+        if let Some(ref mut f) = out {
+            f.flush()?;
+        }
+
+        let calc_checksum = filehasher.finalize().into();
+        if damage.is_none() && src_checksum != Some(calc_checksum) {
+            damage = Some(Damage::Checksum);
+        }
+        let report = SalvageReport {
+            major: self.header.major,
+            minor: self.header.minor,
+            recovered,
+            src_checksum,
+            calc_checksum,
+            damage,
+        };
+        Ok((report, bulk.stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encrypt, plaintext, PASSWORD};
+    use std::io::Cursor;
+
+    const LEN: usize = 1000;
+
+    fn salvage(file: Vec<u8>) -> (SalvageReport, Vec<u8>) {
+        let mut out = Vec::new();
+        let (report, _) = Salvager::new(Cursor::new(file), PASSWORD)
+            .unwrap()
+            .salvage_to(Some(&mut out), NonZeroUsize::new(2).unwrap())
+            .unwrap();
+        assert_eq!(report.recovered, out.len() as u64);
+        (report, out)
+    }
+
+    #[test]
+    fn intact() {
+        let plain = plaintext(LEN);
+        let (report, out) = salvage(encrypt(&plain));
+        assert_eq!(report.damage, None);
+        assert_eq!(report.src_checksum, Some(report.calc_checksum));
+        assert!(out == plain);
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let plain = plaintext(LEN);
+        let mut file = encrypt(&plain);
+        // 31 complete blocks and a partial one
+        file.truncate(DATA_OFFSET as usize + 503);
+        let (report, out) = salvage(file);
+        assert_eq!(report.damage, Some(Damage::Trailer(DATA_OFFSET + 496)));
+        assert_eq!(report.src_checksum, None);
+        assert!(out == plain[..496]);
+
+        // cut on a block boundary
+        let mut file = encrypt(&plain);
+        file.truncate(DATA_OFFSET as usize + 512);
+        let (report, out) = salvage(file);
+        assert_eq!(report.damage, Some(Damage::Trailer(DATA_OFFSET + 512)));
+        assert!(out == plain[..512]);
+    }
+
+    #[test]
+    fn bad_padding() {
+        let plain = plaintext(LEN);
+        let mut file = encrypt(&plain);
+        let last_block = file.len() - 0x20 - 0x10;
+        file[last_block] ^= 1;
+        let (report, out) = salvage(file);
+        assert_eq!(report.damage, Some(Damage::Padding(last_block as u64)));
+        assert_eq!(report.damage.unwrap().offset(), Some(DATA_OFFSET + 992));
+        // the last block is kept raw, padding and all
+        assert_eq!(out.len(), 1008);
+        assert!(out[..992] == plain[..992]);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let plain = plaintext(LEN);
+        let mut file = encrypt(&plain);
+        file[DATA_OFFSET as usize + 100] ^= 1;
+        let (report, out) = salvage(file);
+        assert_eq!(report.damage, Some(Damage::Checksum));
+        assert_eq!(report.damage.unwrap().offset(), None);
+        assert_ne!(report.src_checksum, Some(report.calc_checksum));
+        assert_eq!(out.len(), LEN);
+        // CBC garbles the damaged block and one byte of the next
+        let differing: Vec<_> = (0..LEN).filter(|&i| out[i] != plain[i]).collect();
+        assert!(differing.iter().all(|&i| (96..128).contains(&i)));
+        assert!(out[116] != plain[116]);
+    }
+}
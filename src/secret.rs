@@ -0,0 +1,132 @@
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::slice;
+
+use zeroize::Zeroize;
+
+/// Fixed-size heap buffer for passwords, keys and plaintext
+///
+/// The contents are zeroized on drop. On Unix the buffer is also locked into
+/// RAM with `mlock` so it is never written to swap; locking is best effort and
+/// silently skipped when it fails, e.g. because `RLIMIT_MEMLOCK` is exhausted.
+/// Each buffer gets whole pages of its own, since unlocking one buffer would
+/// otherwise unlock a page still in use by another.
+pub struct SecretBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+    locked: bool,
+}
+
+// SAFETY: SecretBuf owns its allocation exclusively, like a Box<[u8]>
+unsafe impl Send for SecretBuf {}
+unsafe impl Sync for SecretBuf {}
+
+impl SecretBuf {
+    /// Allocates `len` zero bytes.
+    pub fn new(len: usize) -> Self {
+        let page = page_size();
+        let size = len.max(1).div_ceil(page) * page;
+        let layout = Layout::from_size_align(size, page).expect("buffer size overflows");
+        // SAFETY: layout has a non-zero size
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        SecretBuf {
+            ptr,
+            len,
+            layout,
+            locked: lock(ptr, size),
+        }
+    }
+
+    /// Copies `data` into a new buffer.
+    pub fn from_slice(data: &[u8]) -> Self {
+        let mut buf = Self::new(data.len());
+        buf.copy_from_slice(data);
+        buf
+    }
+
+    /// Whether the buffer is locked into RAM
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Deref for SecretBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: ptr points to at least len initialized bytes owned by self
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for SecretBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in deref, and &mut self guarantees exclusive access
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl PartialEq for SecretBuf {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for SecretBuf {}
+
+impl fmt::Debug for SecretBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBuf({} bytes)", self.len)
+    }
+}
+
+impl Drop for SecretBuf {
+    fn drop(&mut self) {
+        // SAFETY: the whole allocation is owned by self and initialized
+        let all = unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) };
+        all.zeroize();
+        if self.locked {
+            unlock(self.ptr, self.layout.size());
+        }
+        // SAFETY: ptr was allocated with layout in new
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    // SAFETY: sysconf has no preconditions
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+#[cfg(not(unix))]
+fn page_size() -> usize {
+    4096
+}
+
+#[cfg(unix)]
+fn lock(ptr: NonNull<u8>, size: usize) -> bool {
+    // SAFETY: the range is a live allocation of whole pages
+    unsafe { libc::mlock(ptr.as_ptr().cast(), size) == 0 }
+}
+
+#[cfg(not(unix))]
+fn lock(_ptr: NonNull<u8>, _size: usize) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn unlock(ptr: NonNull<u8>, size: usize) {
+    // SAFETY: the range was locked by lock and is still allocated
+    unsafe { libc::munlock(ptr.as_ptr().cast(), size) };
+}
+
+#[cfg(not(unix))]
+fn unlock(_ptr: NonNull<u8>, _size: usize) {}
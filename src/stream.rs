@@ -0,0 +1,129 @@
+use std::io::prelude::*;
+
+use digest::Digest;
+use sha1::Sha1;
+
+use crate::{
+    read_full, DecryptSummary, EasyCrabError, EzcHeader, PayloadDecryptor, Result, SecretBuf,
+    CHUNK_SIZE,
+};
+
+/// Bytes held back from the end of the input: the last payload block, which
+/// carries the padding, and the encrypted checksum trailer
+const TAIL_SIZE: usize = 0x10 + 0x20;
+
+/// Decrypter for non-seekable input such as pipes
+///
+/// Unlike [`crate::Decryptor`] the payload length is not known up front, so
+/// the last `TAIL_SIZE` bytes read are held back until the input ends.
+pub struct StreamDecryptor<R> {
+    reader: R,
+    header: EzcHeader,
+}
+
+impl<R: Read> StreamDecryptor<R> {
+    /// Parses the header and checks `password` against the stored verifier.
+    pub fn new(reader: R, password: &[u8]) -> Result<Self> {
+        let dec = Self::from_header_key(reader)?;
+        if !dec.header.check_password(password) {
+            return Err(EasyCrabError::WrongPassword);
+        }
+        Ok(dec)
+    }
+
+    /// Parses the header and decrypts with the key stored in it.
+    pub fn from_header_key(mut reader: R) -> Result<Self> {
+        let header = EzcHeader::read_from(&mut reader)?;
+        Ok(StreamDecryptor { reader, header })
+    }
+
+    pub fn header(&self) -> &EzcHeader {
+        &self.header
+    }
+
+    /// Decrypts the rest of the input into `out`, hashing the plaintext as it goes.
+    pub fn decrypt_to<W: Write>(mut self, mut out: Option<W>) -> Result<DecryptSummary> {
+        let mut payload = PayloadDecryptor::new(&self.header.key(), &self.header.iv);
+        let mut buf = SecretBuf::new(CHUNK_SIZE as usize + TAIL_SIZE);
+        let mut filled = 0;
+        let mut processed_bytes = 0;
+        let mut filehasher = Sha1::new();
+
+        loop {
+            filled += read_full(&mut self.reader, &mut buf[filled..])?;
+            if filled < buf.len() {
+                break;
+            }
+            let chunk = &mut buf[..CHUNK_SIZE as usize];
+            payload.decrypt_chunk(chunk);
+            filehasher.update(&*chunk);
+            if let Some(ref mut f) = out {
+                f.write_all(chunk)?;
+            }
+            buf.copy_within(CHUNK_SIZE as usize.., 0);
+            filled = TAIL_SIZE;
+            processed_bytes += CHUNK_SIZE;
+        }
+
+        if filled < TAIL_SIZE || !(filled - 0x20).is_multiple_of(0x10) {
+            return Err(EasyCrabError::Truncated);
+        }
+        let (last, trailer) = buf[..filled].split_at_mut(filled - 0x20);
+        let data_length = processed_bytes + last.len() as u64;
+        let unpadded = payload.finish(last)?;
+        filehasher.update(unpadded);
+        if let Some(ref mut f) = out {
+            f.write_all(unpadded)?;
+            f.flush()?;
+        }
+
+        let trailer: &[u8; 0x20] = (&*trailer).try_into().unwrap();
+        Ok(DecryptSummary {
+            major: self.header.major,
+            minor: self.header.minor,
+            data_length,
+            plain_length: processed_bytes + unpadded.len() as u64,
+            src_checksum: self.header.decrypt_trailer(trailer)?,
+            calc_checksum: filehasher.finalize().into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encrypt, plaintext, PASSWORD, SIZES};
+
+    #[test]
+    fn round_trip() {
+        // a payload ending exactly on a chunk boundary leaves only the held
+        // back tail in the buffer when the input runs out
+        for len in SIZES.into_iter().chain([2 * CHUNK_SIZE as usize]) {
+            let plain = plaintext(len);
+            let file = encrypt(&plain);
+            let mut out = Vec::new();
+            let summary = StreamDecryptor::new(&file[..], PASSWORD)
+                .unwrap()
+                .decrypt_to(Some(&mut out))
+                .unwrap();
+            assert!(summary.is_match(), "length {}", len);
+            assert_eq!(summary.plain_length, len as u64);
+            assert!(out == plain, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let file = encrypt(&plaintext(CHUNK_SIZE as usize));
+        for len in [file.len() - 0x10 - 1, file.len() - 1] {
+            let result = StreamDecryptor::new(&file[..len], PASSWORD)
+                .unwrap()
+                .decrypt_to(None::<Vec<u8>>);
+            assert!(
+                matches!(result, Err(EasyCrabError::Truncated)),
+                "length {}",
+                len
+            );
+        }
+    }
+}